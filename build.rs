@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::{env, fs, slice, str};
 
+use windows::core::PCSTR;
 use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
 use windows::Win32::Graphics::Direct3D::ID3DBlob;
 use windows::s;
@@ -8,45 +9,40 @@ use windows::s;
 fn main() -> Result<(), Box<dyn Error + 'static>> {
     static VERTEX_SHADER: &str = include_str!("src/vertex_shader.vs_4_0");
     static PIXEL_SHADER: &str = include_str!("src/pixel_shader.ps_4_0");
-
-    let mut err = None; // Never used, but left in-case inspection later is needed
-    let mut vs_blob = None;
-    let mut ps_blob = None;
+    static PIXEL_SHADER_HDR: &str = include_str!("src/pixel_shader_hdr.ps_4_0");
+    static PIXEL_SHADER_SRGB: &str = include_str!("src/pixel_shader_srgb.ps_4_0");
 
     unsafe {
-        D3DCompile(
-            VERTEX_SHADER.as_ptr() as _,
-            VERTEX_SHADER.len(),
-            None,
-            None,
-            None,
-            s!("main"),
-            s!("vs_4_0"),
-            0,
-            0,
-            &mut vs_blob,
-            Some(&mut err),
-        )?;
-        if let Some(vs_blob) = vs_blob.as_ref() {
-            write_blob("vertex_shader.vs_4_0", vs_blob)?;
-        }
+        compile_shader("vertex_shader.vs_4_0", VERTEX_SHADER, s!("vs_4_0"))?;
+        compile_shader("pixel_shader.ps_4_0", PIXEL_SHADER, s!("ps_4_0"))?;
+        compile_shader("pixel_shader_hdr.ps_4_0", PIXEL_SHADER_HDR, s!("ps_4_0"))?;
+        compile_shader("pixel_shader_srgb.ps_4_0", PIXEL_SHADER_SRGB, s!("ps_4_0"))?;
+    }
+    Ok(())
+}
 
-        D3DCompile(
-            PIXEL_SHADER.as_ptr() as _,
-            PIXEL_SHADER.len(),
-            None,
-            None,
-            None,
-            s!("main"),
-            s!("ps_4_0"),
-            0,
-            0,
-            &mut ps_blob,
-            Some(&mut err),
-        )?;
-        if let Some(ps_blob) = ps_blob.as_ref() {
-            write_blob("pixel_shader.ps_4_0", ps_blob)?;
-        }
+unsafe fn compile_shader(
+    shader_name: &str,
+    source: &str,
+    target: PCSTR,
+) -> Result<(), Box<dyn Error + 'static>> {
+    let mut err = None; // Never used, but left in-case inspection later is needed
+    let mut blob = None;
+    D3DCompile(
+        source.as_ptr() as _,
+        source.len(),
+        None,
+        None,
+        None,
+        s!("main"),
+        target,
+        0,
+        0,
+        &mut blob,
+        Some(&mut err),
+    )?;
+    if let Some(blob) = blob.as_ref() {
+        write_blob(shader_name, blob)?;
     }
     Ok(())
 }
@@ -78,18 +78,6 @@ fn d3d11_initialize(
     Ok((device.unwrap(), swapchain.unwrap(), device_context.unwrap()))
 }
 
-fn create_render_target(
-    swapchain: &IDXGISwapChain,
-    device: &ID3D11Device,
-) -> Result<ID3D11RenderTargetView> {
-    unsafe {
-        let backbuffer: ID3D11Resource = swapchain.GetBuffer(0)?;
-        let mut render_target = None;
-        device.CreateRenderTargetView(&backbuffer, None, Some(&mut render_target))?;
-        Ok(render_target.unwrap())
-    }
-}
-
 fn main() -> Result<()> {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -98,9 +86,12 @@ fn main() -> Result<()> {
         .build(&event_loop)
         .unwrap();
 
-    let (device, swapchain, device_ctx) = d3d11_initialize(HWND(window.hwnd()))?;
+    let (device, swapchain, _device_ctx) = d3d11_initialize(HWND(window.hwnd()))?;
 
-    let mut target = Some(create_render_target(&swapchain, &device)?);
+    let mut target = Some(
+        Renderer::create_backbuffer_rtv(&device, &swapchain)
+            .expect("render target creation failed"),
+    );
 
     let mut imgui = Context::create();
     let mut platform = WinitPlatform::init(&mut imgui);
@@ -114,7 +105,8 @@ fn main() -> Result<()> {
         config: Some(FontConfig { size_pixels: font_size, ..FontConfig::default() }),
     }]);
 
-    let mut renderer = Renderer::new(&mut imgui, &device)?;
+    let mut renderer =
+        Renderer::new(&mut imgui, &device).expect("imgui dx11 renderer creation failed");
     let mut last_frame = Instant::now();
 
     event_loop.run(move |event, _, control_flow| match event {
@@ -129,13 +121,7 @@ fn main() -> Result<()> {
             window.request_redraw();
         },
         Event::RedrawRequested(_) => {
-            unsafe {
-                device_ctx.OMSetRenderTargets(Some(&[target.clone().unwrap()]), None);
-                device_ctx.ClearRenderTargetView(
-                    target.as_ref().unwrap(),
-                    &[0.0 as f32, 0.0, 1.0, 1.0] as *const f32,
-                );
-            }
+            renderer.begin_frame(target.as_ref().unwrap(), None, [0.0, 0.0, 1.0, 1.0]);
             let ui = imgui.frame();
             ui.window("Hello world").size([300.0, 100.0], imgui::Condition::FirstUseEver).build(
                 || {
@@ -165,7 +151,7 @@ fn main() -> Result<()> {
             unsafe {
                 swapchain.ResizeBuffers(0, width, height, DXGI_FORMAT_UNKNOWN, 0).unwrap();
             }
-            target = create_render_target(&swapchain, &device).ok();
+            target = Renderer::create_backbuffer_rtv(&device, &swapchain).ok();
             platform.handle_event(imgui.io_mut(), &window, &event);
         },
         Event::LoopDestroyed => (),
@@ -2,96 +2,1069 @@
 #![deny(missing_docs)]
 #![no_std]
 //! This crate offers a DirectX 11 renderer for the [imgui-rs](https://docs.rs/imgui/*/imgui/) rust bindings.
+//!
+//! Devices of feature level 10.0 and up are supported, as required by the
+//! embedded shader model 4.0 shaders.
+//!
+//! The crate is `no_std` by default. Enabling the `image` feature links in
+//! `std`, as decoding images from files requires file system access.
 
-use alloc::string::ToString;
+#[cfg(feature = "image")]
+extern crate std;
+
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
-use core::{mem, slice};
+use core::cell::RefCell;
+use core::{fmt, iter, mem, ptr, slice};
 
-use imgui::internal::RawWrapper;
+use imgui::internal::{RawCast, RawWrapper};
 use imgui::{
-    BackendFlags, DrawCmd, DrawCmdParams, DrawData, DrawIdx, DrawVert, TextureId, Textures,
+    BackendFlags, DrawCmd, DrawCmdParams, DrawData, DrawIdx, DrawList, DrawVert, TextureId,
+    Textures,
 };
 use windows::core::*;
-use windows::Win32::Foundation::RECT;
+use windows::Win32::Foundation::{BOOL, RECT};
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
 use windows::Win32::Graphics::Dxgi::*;
 
-type Result<T> = windows::core::Result<T>;
+type Result<T> = core::result::Result<T, RendererError>;
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::warn!($($arg)*);
+    };
+}
+
+/// The error type returned by the renderer.
+///
+/// Empty draw data is not an error, see [`Renderer::render`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RendererError {
+    /// The draw data referenced a texture that isn't registered.
+    InvalidTextureId(TextureId),
+    /// The pixel data passed for a texture doesn't match its dimensions.
+    TextureDataSize {
+        /// The expected size in bytes.
+        expected: usize,
+        /// The size in bytes of the data that was passed.
+        actual: usize,
+    },
+    /// The texture dimensions exceed the maximum supported by the device's
+    /// feature level.
+    TextureTooLarge {
+        /// The requested width.
+        width: u32,
+        /// The requested height.
+        height: u32,
+        /// The maximum width and height supported by the device.
+        max: u32,
+    },
+    /// The dimensions of a block compressed texture aren't multiples of the
+    /// block size of 4.
+    UnalignedTextureSize {
+        /// The requested width.
+        width: u32,
+        /// The requested height.
+        height: u32,
+    },
+    /// More mip levels were passed for a texture than it has.
+    TooManyMipLevels {
+        /// The number of levels that were passed.
+        levels: usize,
+        /// The number of levels of a full mip chain for the texture.
+        max: u32,
+    },
+    /// A texture with a typeless format was passed, which requires an explicit
+    /// view format.
+    TypelessFormat(DXGI_FORMAT),
+    /// The draw data needs a buffer larger than Direct3D can address, or than
+    /// the limit set via [`RendererBuilder::buffer_limit`].
+    BufferTooLarge {
+        /// The number of elements that were requested.
+        len: usize,
+    },
+    /// The constant buffer passed to [`RendererBuilder::vertex_constant_buffer`]
+    /// is too small or not a dynamic constant buffer writable by the CPU.
+    InvalidConstantBuffer,
+    /// The stride passed to [`RendererBuilder::vertex_stride`] is smaller than
+    /// a [`DrawVert`].
+    InvalidVertexStride {
+        /// The stride that was passed.
+        stride: usize,
+        /// The size of a [`DrawVert`].
+        min: usize,
+    },
+    /// An object supplied to the renderer belongs to another device than the
+    /// one the device objects are created on, e.g. a sampler passed to
+    /// [`RendererBuilder::font_sampler`] or a buffer passed to
+    /// [`RendererBuilder::vertex_constant_buffer`] before the device was lost.
+    DeviceMismatch,
+    /// The device is below feature level 10.0, the minimum required by the
+    /// embedded shaders.
+    UnsupportedFeatureLevel(D3D_FEATURE_LEVEL),
+    /// The device was removed or reset and all device objects have to be
+    /// recreated, see [`Renderer::create_device_objects`].
+    DeviceLost(Error),
+    /// Decoding an image failed.
+    #[cfg(feature = "image")]
+    Image(image::ImageError),
+    /// Any other error reported by Direct3D.
+    Windows(Error),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::InvalidTextureId(id) => write!(f, "invalid texture id {}", id.id()),
+            RendererError::TextureDataSize { expected, actual } => {
+                write!(f, "expected {} bytes of texture data, got {}", expected, actual)
+            },
+            RendererError::TextureTooLarge { width, height, max } => {
+                write!(f, "a {}x{} texture exceeds the maximum dimension of {}", width, height, max)
+            },
+            RendererError::UnalignedTextureSize { width, height } => {
+                write!(
+                    f,
+                    "a {}x{} compressed texture isn't a multiple of 4x4 blocks",
+                    width, height
+                )
+            },
+            RendererError::TooManyMipLevels { levels, max } => {
+                write!(f, "{} mip levels exceed the full mip chain of {} levels", levels, max)
+            },
+            RendererError::TypelessFormat(format) => {
+                write!(f, "typeless texture format {} requires an explicit view format", format.0)
+            },
+            RendererError::BufferTooLarge { len } => {
+                write!(f, "a buffer of {} elements exceeds the maximum byte width", len)
+            },
+            RendererError::InvalidConstantBuffer => {
+                write!(f, "vertex constant buffer isn't dynamic, CPU writable or large enough")
+            },
+            RendererError::InvalidVertexStride { stride, min } => {
+                write!(f, "vertex stride {} is smaller than a vertex of {} bytes", stride, min)
+            },
+            RendererError::DeviceMismatch => {
+                write!(f, "object belongs to another device than the renderer's")
+            },
+            RendererError::UnsupportedFeatureLevel(level) => {
+                write!(f, "unsupported feature level {:#x}, at least 10.0 is required", level.0)
+            },
+            RendererError::DeviceLost(e) => write!(f, "device lost: {}", e),
+            #[cfg(feature = "image")]
+            RendererError::Image(e) => write!(f, "failed to decode image: {}", e),
+            RendererError::Windows(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            RendererError::DeviceLost(e) | RendererError::Windows(e) => Some(e),
+            #[cfg(feature = "image")]
+            RendererError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for RendererError {
+    fn from(error: Error) -> Self {
+        match error.code() {
+            DXGI_ERROR_DEVICE_REMOVED | DXGI_ERROR_DEVICE_RESET | DXGI_ERROR_DEVICE_HUNG => {
+                log_warn!("device lost: {}", error);
+                RendererError::DeviceLost(error)
+            },
+            _ => RendererError::Windows(error),
+        }
+    }
+}
 
 const FONT_TEX_ID: usize = !0;
 
+/// The values of `ImDrawCallback_ResetRenderState`, `-1` in older versions of
+/// imgui and `-8` since.
+const RESET_RENDER_STATE_CALLBACKS: [isize; 2] = [-1, -8];
+
+// imgui can be compiled with either 16 or 32 bit indices (`ImDrawIdx`), the
+// index buffer format and byte widths are derived from whichever is in use.
+const _: () = assert!(
+    mem::size_of::<DrawIdx>() == 2 || mem::size_of::<DrawIdx>() == 4,
+    "DrawIdx must be either 16 or 32 bits wide"
+);
+const INDEX_FORMAT: DXGI_FORMAT =
+    if mem::size_of::<DrawIdx>() == 2 { DXGI_FORMAT_R16_UINT } else { DXGI_FORMAT_R32_UINT };
+
+const IDENTITY_MATRIX: [[f32; 4]; 4] =
+    [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
 const VERTEX_BUF_ADD_CAPACITY: usize = 5000;
 const INDEX_BUF_ADD_CAPACITY: usize = 10000;
 
+const VERTEX_SHADER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/vertex_shader.vs_4_0"));
+const PIXEL_SHADER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/pixel_shader.ps_4_0"));
+const PIXEL_SHADER_HDR: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/pixel_shader_hdr.ps_4_0"));
+const PIXEL_SHADER_SRGB: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/pixel_shader_srgb.ps_4_0"));
+
 #[repr(C)]
 struct VertexConstantBuffer {
     mvp: [[f32; 4]; 4],
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PixelConstantBuffer {
+    tint: [f32; 4],
+    sdr_white_level: f32,
+    gamma: f32,
+    brightness: f32,
+    _padding: f32,
+    // Rows padded to a `float4` each, as HLSL aligns every array element.
+    color_matrix: [[f32; 4]; 3],
+}
+
+/// The filter used when sampling the font texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextureFilter {
+    /// Linear filtering, also blending between mip levels if present.
+    #[default]
+    Linear,
+    /// Nearest neighbor filtering, for pixel fonts and crisp 1:1 UIs.
+    Point,
+}
+
+impl TextureFilter {
+    fn to_d3d11(self) -> D3D11_FILTER {
+        match self {
+            TextureFilter::Linear => D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+            TextureFilter::Point => D3D11_FILTER_MIN_MAG_MIP_POINT,
+        }
+    }
+}
+
+/// Diagnostic information about the adapter and feature level a renderer runs
+/// on, see [`Renderer::adapter_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterInfo {
+    /// The description of the adapter, usually the name of the GPU.
+    pub name: String,
+    /// The PCI vendor id of the adapter.
+    pub vendor_id: u32,
+    /// The PCI device id of the adapter.
+    pub device_id: u32,
+    /// The dedicated video memory of the adapter in bytes.
+    pub dedicated_video_memory: usize,
+    /// The feature level of the device.
+    pub feature_level: D3D_FEATURE_LEVEL,
+}
+
+/// Statistics about the geometry submitted by a render call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// The number of draw calls issued.
+    pub draw_calls: u32,
+    /// The number of indices drawn, excluding those of clipped commands.
+    pub indices_drawn: u32,
+    /// The number of vertices in the draw data.
+    pub total_vertices: u32,
+    /// The number of indices in the draw data.
+    pub total_indices: u32,
+    /// The number of times a different texture had to be bound.
+    pub texture_binds: u32,
+}
+
+/// The kind of render target imgui is rendered into, selecting how its sRGB
+/// colors are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetFormat {
+    /// Colors are written unchanged, for targets without automatic sRGB
+    /// conversion like `DXGI_FORMAT_R8G8B8A8_UNORM`.
+    Unorm,
+    /// Colors are linearized, as the hardware converts them back when writing
+    /// to targets like `DXGI_FORMAT_R8G8B8A8_UNORM_SRGB`.
+    Srgb,
+    /// Colors are linearized and scaled by the SDR white level, for linear
+    /// scRGB targets like `DXGI_FORMAT_R16G16B16A16_FLOAT`, see
+    /// [`RendererBuilder::hdr_output`].
+    Hdr,
+}
+
+/// A block compressed format for [`Renderer::register_compressed_texture`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DxgiCompressedFormat {
+    /// `DXGI_FORMAT_BC1_UNORM`, 8 bytes per 4x4 block with 1 bit alpha.
+    Bc1,
+    /// `DXGI_FORMAT_BC3_UNORM`, 16 bytes per 4x4 block with smooth alpha.
+    Bc3,
+    /// `DXGI_FORMAT_BC7_UNORM`, 16 bytes per 4x4 block in high quality.
+    /// Requires feature level 11.0.
+    Bc7,
+}
+
+impl DxgiCompressedFormat {
+    fn to_dxgi(self) -> DXGI_FORMAT {
+        match self {
+            DxgiCompressedFormat::Bc1 => DXGI_FORMAT_BC1_UNORM,
+            DxgiCompressedFormat::Bc3 => DXGI_FORMAT_BC3_UNORM,
+            DxgiCompressedFormat::Bc7 => DXGI_FORMAT_BC7_UNORM,
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            DxgiCompressedFormat::Bc1 => 8,
+            DxgiCompressedFormat::Bc3 | DxgiCompressedFormat::Bc7 => 16,
+        }
+    }
+}
+
+/// How imgui's output is blended onto the render target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Straight alpha blending, `src * src_alpha + dst * (1 - src_alpha)`.
+    #[default]
+    Straight,
+    /// Premultiplied alpha blending, `src + dst * (1 - src_alpha)`.
+    ///
+    /// Use this when the colors produced by the pixel shader are already
+    /// premultiplied, e.g. with premultiplied textures or a custom pixel shader
+    /// when compositing onto DirectComposition visuals or layered windows.
+    Premultiplied,
+}
+
+/// A builder to configure and create a [`Renderer`].
+///
+/// ```ignore
+/// let renderer = Renderer::builder().font_mipmaps(true).build(&mut imgui, &device)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RendererBuilder {
+    font_mipmaps: bool,
+    font_filter: TextureFilter,
+    font_sampler: Option<ID3D11SamplerState>,
+    blend_mode: BlendMode,
+    depth_func: Option<D3D11_COMPARISON_FUNC>,
+    sdr_white_level: Option<f32>,
+    context: Option<ID3D11DeviceContext>,
+    disable_scissor: bool,
+    buffer_headroom: Option<(usize, usize)>,
+    buffer_limit: Option<(usize, usize)>,
+    antialiased_lines: bool,
+    vertex_stride: Option<usize>,
+    alpha_blend: Option<(D3D11_BLEND, D3D11_BLEND, D3D11_BLEND_OP)>,
+    linear_blending: bool,
+    texture_anisotropy: Option<u32>,
+    gpu_timing: bool,
+    keep_renderer_name: bool,
+    keep_shader_stages: bool,
+    vertex_constant_buffer: Option<ID3D11Buffer>,
+}
+
+impl RendererBuilder {
+    /// Creates a builder with the default configuration.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the font texture with a full mip chain.
+    ///
+    /// This avoids shimmering glyphs when the UI is rendered at a smaller scale
+    /// than the font atlas was baked at. Disabled by default.
+    #[inline]
+    pub fn font_mipmaps(mut self, enable: bool) -> Self {
+        self.font_mipmaps = enable;
+        self
+    }
+
+    /// Sets the filter used for sampling the font texture.
+    ///
+    /// Defaults to [`TextureFilter::Linear`].
+    #[inline]
+    pub fn font_filter(mut self, filter: TextureFilter) -> Self {
+        self.font_filter = filter;
+        self
+    }
+
+    /// Samples the font texture, and all textures unless
+    /// [`RendererBuilder::texture_anisotropy`] is set, with the given sampler
+    /// instead of creating one, e.g. to share the samplers of a larger
+    /// renderer.
+    ///
+    /// The sampler must belong to the device the device objects are created
+    /// on, otherwise [`RendererError::DeviceMismatch`] is returned. After the
+    /// device was lost, pass a sampler of the new device to
+    /// [`Renderer::set_font_sampler`]. [`RendererBuilder::font_filter`] is
+    /// ignored. Defaults to a sampler created by the renderer.
+    #[inline]
+    pub fn font_sampler(mut self, sampler: ID3D11SamplerState) -> Self {
+        self.font_sampler = Some(sampler);
+        self
+    }
+
+    /// Samples registered textures with anisotropic filtering of up to
+    /// `max_anisotropy` samples, between 1 and 16, e.g. for images scaled up in
+    /// large image widgets.
+    ///
+    /// The font texture keeps using the filter set via
+    /// [`RendererBuilder::font_filter`]. Disabled by default, all textures are
+    /// sampled like the font then.
+    #[inline]
+    pub fn texture_anisotropy(mut self, max_anisotropy: u32) -> Self {
+        self.texture_anisotropy = Some(max_anisotropy);
+        self
+    }
+
+    /// Sets how imgui's output is blended onto the render target.
+    ///
+    /// Defaults to [`BlendMode::Straight`].
+    #[inline]
+    pub fn blend_mode(mut self, mode: BlendMode) -> Self {
+        self.blend_mode = mode;
+        self
+    }
+
+    /// Sets the source and destination factors and the operation used for
+    /// blending the alpha channel, independently of the color channels.
+    ///
+    /// Useful when rendering into a texture whose alpha is used for
+    /// compositing later on. Defaults to `D3D11_BLEND_ONE`,
+    /// `D3D11_BLEND_INV_SRC_ALPHA` and `D3D11_BLEND_OP_ADD`.
+    #[inline]
+    pub fn alpha_blend(mut self, src: D3D11_BLEND, dest: D3D11_BLEND, op: D3D11_BLEND_OP) -> Self {
+        self.alpha_blend = Some((src, dest, op));
+        self
+    }
+
+    /// Enables depth testing of imgui geometry against the bound depth buffer
+    /// with the given comparison function.
+    ///
+    /// All imgui geometry lands at a depth of `0.5`, which can be changed via
+    /// [`Renderer::set_depth`]. Depth writes stay
+    /// disabled, so overlapping imgui elements don't occlude each other.
+    /// Disabled by default.
+    #[inline]
+    pub fn depth_test(mut self, func: Option<D3D11_COMPARISON_FUNC>) -> Self {
+        self.depth_func = func;
+        self
+    }
+
+    /// Enables output for HDR render targets in linear scRGB, like
+    /// `DXGI_FORMAT_R16G16B16A16_FLOAT` swapchains.
+    ///
+    /// The sRGB colors of imgui are linearized and scaled by
+    /// `sdr_white_level`, the brightness of SDR white in scRGB units where
+    /// `1.0` corresponds to 80 nits. It can be adjusted later on via
+    /// [`Renderer::set_sdr_white_level`]. Disabled by default.
+    #[inline]
+    pub fn hdr_output(mut self, sdr_white_level: f32) -> Self {
+        self.sdr_white_level = Some(sdr_white_level);
+        self
+    }
+
+    /// Blends imgui's colors in linear space instead of blending the sRGB
+    /// encoded values directly, which is technically incorrect but what imgui
+    /// is designed and tuned for.
+    ///
+    /// The pixel shader linearizes its output, so this requires rendering
+    /// into a render target view with an `_SRGB` format, which encodes the
+    /// blended result back to sRGB. This costs a few extra instructions per
+    /// pixel and makes translucent elements look lighter than intended by
+    /// imgui's styles. The same can be selected per call with
+    /// [`TargetFormat::Srgb`]. Disabled by default.
+    #[inline]
+    pub fn linear_blending(mut self, enable: bool) -> Self {
+        self.linear_blending = enable;
+        self
+    }
+
+    /// Sets the number of vertices and indices the vertex and index buffers
+    /// are allocated with on top of what a frame needs when they have to grow.
+    ///
+    /// Larger headroom avoids frequent reallocations for spiky geometry, while
+    /// steady geometry can get away with less memory. Defaults to 5000
+    /// vertices and 10000 indices.
+    #[inline]
+    pub fn buffer_headroom(mut self, vertices: usize, indices: usize) -> Self {
+        self.buffer_headroom = Some((vertices, indices));
+        self
+    }
+
+    /// Sets the maximum number of vertices and indices a frame may submit.
+    ///
+    /// Draw data exceeding either makes rendering fail with
+    /// [`RendererError::BufferTooLarge`] instead of growing the buffers, as a
+    /// safety valve against runaway UIs exhausting GPU memory. Unlimited by
+    /// default.
+    #[inline]
+    pub fn buffer_limit(mut self, max_vertices: usize, max_indices: usize) -> Self {
+        self.buffer_limit = Some((max_vertices, max_indices));
+        self
+    }
+
+    /// Enables clipping draw commands to their clip rects with the scissor test.
+    ///
+    /// Disabling it skips setting a scissor rect for each draw command, which
+    /// is useful when rendering into a fixed region with a scissor set up by
+    /// the application. Note that windows dragged outside of the display
+    /// won't be clipped then. Enabled by default.
+    #[inline]
+    pub fn scissor_test(mut self, enable: bool) -> Self {
+        self.disable_scissor = !enable;
+        self
+    }
+
+    /// Sets the size in bytes of a vertex, for imgui builds with a custom
+    /// `ImDrawVert` carrying extra per-vertex data after the standard fields.
+    ///
+    /// The vertices are uploaded with this stride and must be consumed by a
+    /// vertex shader and input layout set via [`Renderer::set_vertex_shader`].
+    /// Strides below the size of [`DrawVert`] make [`RendererBuilder::build`]
+    /// return [`RendererError::InvalidVertexStride`]. Defaults to the size of
+    /// [`DrawVert`].
+    ///
+    /// # Safety
+    ///
+    /// `stride` must be the size of `ImDrawVert` in the imgui build the draw
+    /// data comes from, as the vertices of each draw list are read as
+    /// `stride` bytes each.
+    #[inline]
+    pub unsafe fn vertex_stride(mut self, stride: usize) -> Self {
+        self.vertex_stride = Some(stride);
+        self
+    }
+
+    /// Enables `AntialiasedLineEnable` on the rasterizer, smoothing line
+    /// primitives on render targets without MSAA.
+    ///
+    /// Note that this only affects line primitives, e.g. drawn by raw
+    /// callbacks. imgui itself always emits triangles and antialiases its
+    /// lines through the `anti_aliased_lines` and `anti_aliased_lines_use_tex`
+    /// style flags, which should be kept enabled for smooth UI lines. Disabled
+    /// by default.
+    #[inline]
+    pub fn antialiased_lines(mut self, enable: bool) -> Self {
+        self.antialiased_lines = enable;
+        self
+    }
+
+    /// Measures the GPU time of each render call with timestamp queries,
+    /// reported by [`Renderer::last_gpu_time`].
+    ///
+    /// The queries are read back a few frames later so that profiling never
+    /// stalls the CPU, and only when rendering with the immediate context.
+    /// Disabled by default.
+    #[inline]
+    pub fn gpu_timing(mut self, enable: bool) -> Self {
+        self.gpu_timing = enable;
+        self
+    }
+
+    /// Sets whether the geometry, hull, domain and compute shaders are unbound
+    /// before drawing.
+    ///
+    /// Disable it to keep the stages of the application or of raw callbacks
+    /// interleaving their own passes with imgui's draws bound. Any geometry or
+    /// tessellation shader left bound then also processes imgui's geometry and
+    /// will likely corrupt it. Enabled by default.
+    #[inline]
+    pub fn unbind_shader_stages(mut self, enable: bool) -> Self {
+        self.keep_shader_stages = !enable;
+        self
+    }
+
+    /// Sets whether the renderer name of the imgui context is set to this
+    /// crate on creation.
+    ///
+    /// Disable it to keep a name set by the application, e.g. in setups with
+    /// several renderers. Enabled by default.
+    #[inline]
+    pub fn set_renderer_name(mut self, enable: bool) -> Self {
+        self.keep_renderer_name = !enable;
+        self
+    }
+
+    /// Writes the projection into the given constant buffer instead of
+    /// creating one, e.g. to allocate it from the application's own constant
+    /// buffer management.
+    ///
+    /// The buffer must be a dynamic constant buffer with CPU write access of
+    /// at least 64 bytes, otherwise [`RendererError::InvalidConstantBuffer`]
+    /// is returned. It must belong to the device the device objects are
+    /// created on, otherwise [`RendererError::DeviceMismatch`] is returned.
+    /// After the device was lost, pass a buffer of the new device to
+    /// [`Renderer::set_vertex_constant_buffer`]. Defaults to a buffer created
+    /// by the renderer.
+    #[inline]
+    pub fn vertex_constant_buffer(mut self, buffer: ID3D11Buffer) -> Self {
+        self.vertex_constant_buffer = Some(buffer);
+        self
+    }
+
+    /// Renders into the given device context instead of the device's
+    /// immediate context.
+    ///
+    /// This can be a deferred context, in which case the recorded commands are
+    /// retrieved with [`Renderer::finish_command_list`] and executed on the
+    /// immediate context later on. The font texture is always uploaded via the
+    /// immediate context.
+    #[inline]
+    pub fn device_context(mut self, context: ID3D11DeviceContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Creates a new renderer for the given [`ID3D11Device`].
+    pub fn build(self, im_ctx: &mut imgui::Context, device: &ID3D11Device) -> Result<Renderer> {
+        let min_stride = mem::size_of::<DrawVert>();
+        if let Some(stride) = self.vertex_stride.filter(|&stride| stride < min_stride) {
+            return Err(RendererError::InvalidVertexStride { stride, min: min_stride });
+        }
+        let immediate_context = unsafe { device.GetImmediateContext()? };
+        let (vertex_headroom, index_headroom) =
+            self.buffer_headroom.unwrap_or((VERTEX_BUF_ADD_CAPACITY, INDEX_BUF_ADD_CAPACITY));
+        let mut renderer = Renderer {
+            device: device.clone(),
+            context: self.context.clone().unwrap_or(immediate_context),
+            objects: None,
+            custom_vertex_shader: None,
+            custom_pixel_shader: None,
+            target_format: None,
+            pixel_constants: PixelConstantBuffer {
+                tint: [1.0; 4],
+                sdr_white_level: self.sdr_white_level.unwrap_or(1.0),
+                gamma: 1.0,
+                brightness: 1.0,
+                _padding: 0.0,
+                color_matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]],
+            },
+            pixel_constants_dirty: false,
+            custom_blend_state: None,
+            custom_rasterizer_state: None,
+            on_reset_render_state: None,
+            before_draw_list: None,
+            after_draw_list: None,
+            textures: Textures::new(),
+            transform: IDENTITY_MATRIX,
+            depth: 0.5,
+            flip_y: false,
+            wireframe: false,
+            viewport_offset: [0.0, 0.0],
+            render_viewport: None,
+            scissor_bounds: None,
+            last_mvp: None,
+            state_backup: StateBackup::default(),
+            last_stats: RenderStats::default(),
+            last_draw_list_count: 0,
+            scissor_test: !self.disable_scissor,
+            dropped_textures: Rc::default(),
+            free_texture_ids: Vec::new(),
+            texture_generations: Vec::new(),
+            texture_id_bound: 0,
+            vertex_headroom,
+            index_headroom,
+            vertex_stride: self.vertex_stride.unwrap_or(mem::size_of::<DrawVert>()),
+            config: self,
+        };
+        renderer.create_device_objects(im_ctx, device)?;
+
+        im_ctx.io_mut().backend_flags |= Renderer::BACKEND_FLAGS;
+        if !renderer.config.keep_renderer_name {
+            let renderer_name = concat!("imgui_dx11_renderer@", env!("CARGO_PKG_VERSION"));
+            im_ctx.set_renderer_name(Some(renderer_name.to_string()));
+        }
+        Ok(renderer)
+    }
+}
+
 /// A DirectX 11 renderer for (Imgui-rs)[https://docs.rs/imgui/*/imgui/].
+///
+/// # Threading
+///
+/// The renderer holds its device context, which Direct3D doesn't allow to be
+/// used from several threads at once, so it is neither `Send` nor `Sync`. Use
+/// [`SendRenderer`] to move it to a dedicated render thread.
+///
+/// # Device loss
+///
+/// When rendering fails with [`RendererError::DeviceLost`], call
+/// [`Renderer::invalidate_device_objects`], recreate the device and pass it to
+/// [`Renderer::create_device_objects`]. The configuration of the renderer is
+/// kept, while textures have to be registered again on the new device, just
+/// like objects supplied via the builder have to be supplied again.
 #[derive(Debug)]
 pub struct Renderer {
     device: ID3D11Device,
     context: ID3D11DeviceContext,
+    objects: Option<DeviceObjects>,
+    custom_vertex_shader: Option<(ID3D11VertexShader, ID3D11InputLayout)>,
+    custom_pixel_shader: Option<ID3D11PixelShader>,
+    target_format: Option<TargetFormat>,
+    pixel_constants: PixelConstantBuffer,
+    pixel_constants_dirty: bool,
+    custom_blend_state: Option<ID3D11BlendState>,
+    custom_rasterizer_state: Option<ID3D11RasterizerState>,
+    on_reset_render_state: Option<Callback<dyn Fn(&ID3D11DeviceContext)>>,
+    before_draw_list: Option<Callback<dyn Fn(usize, &ID3D11DeviceContext)>>,
+    after_draw_list: Option<Callback<dyn Fn(usize, &ID3D11DeviceContext)>>,
+    textures: Textures<ID3D11ShaderResourceView>,
+    transform: [[f32; 4]; 4],
+    depth: f32,
+    flip_y: bool,
+    wireframe: bool,
+    viewport_offset: [f32; 2],
+    render_viewport: Option<D3D11_VIEWPORT>,
+    scissor_bounds: Option<RECT>,
+    last_mvp: Option<[[f32; 4]; 4]>,
+    state_backup: StateBackup,
+    last_stats: RenderStats,
+    last_draw_list_count: usize,
+    scissor_test: bool,
+    dropped_textures: Rc<RefCell<Vec<(TextureId, u32)>>>,
+    free_texture_ids: Vec<TextureId>,
+    // Bumped whenever the texture of an id is removed, so that a handle
+    // dropped after its id was reused doesn't remove the new texture.
+    texture_generations: Vec<u32>,
+    texture_id_bound: usize,
+    vertex_headroom: usize,
+    index_headroom: usize,
+    vertex_stride: usize,
+    config: RendererBuilder,
+}
+
+/// The objects the renderer creates on its device, dropped as a whole by
+/// [`Renderer::invalidate_device_objects`].
+#[derive(Debug)]
+struct DeviceObjects {
     vertex_shader: ID3D11VertexShader,
-    pixel_shader: ID3D11PixelShader,
     input_layout: ID3D11InputLayout,
     constant_buffer: ID3D11Buffer,
+    pixel_shader: ID3D11PixelShader,
+    format_pixel_shaders: [ID3D11PixelShader; 3],
+    pixel_constant_buffer: ID3D11Buffer,
     blend_state: ID3D11BlendState,
     rasterizer_state: ID3D11RasterizerState,
+    wireframe_rasterizer_state: ID3D11RasterizerState,
     depth_stencil_state: ID3D11DepthStencilState,
     font_resource_view: ID3D11ShaderResourceView,
     font_sampler: ID3D11SamplerState,
+    font_texture_size: [u32; 2],
+    font_atlas_key: FontAtlasKey,
+    texture_sampler: Option<ID3D11SamplerState>,
+    gpu_timer: Option<GpuTimer>,
     vertex_buffer: Buffer,
     index_buffer: Buffer,
-    textures: Textures<ID3D11ShaderResourceView>,
+}
+
+/// Identifies the font atlas the font texture was built from, to notice when
+/// the application rebuilt it or switched contexts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FontAtlasKey {
+    atlas: *const imgui::sys::ImFontAtlas,
+    font_count: i32,
+    size: [i32; 2],
+}
+
+impl FontAtlasKey {
+    fn new(fonts: &imgui::FontAtlas) -> Self {
+        let raw = unsafe { fonts.raw() };
+        FontAtlasKey { atlas: raw, font_count: raw.Fonts.Size, size: [raw.TexWidth, raw.TexHeight] }
+    }
 }
 
 impl Renderer {
-    /// Creates a new renderer for the given [`ID3D11Device`].
+    /// The backend flags set by the renderer on creation.
+    ///
+    /// `RENDERER_HAS_VTX_OFFSET`: draw commands are issued with their vertex
+    /// offset, allowing meshes with more than 64k vertices with 16 bit indices.
+    pub const BACKEND_FLAGS: BackendFlags = BackendFlags::RENDERER_HAS_VTX_OFFSET;
+
+    /// A color matrix simulating protanopia, red blindness, for
+    /// [`Renderer::set_color_matrix`], after Machado et al. 2009.
+    pub const PROTANOPIA: [[f32; 3]; 3] = [
+        [0.152286, 1.052583, -0.204868],
+        [0.114503, 0.786281, 0.099216],
+        [-0.003882, -0.048116, 1.051998],
+    ];
+
+    /// A color matrix simulating deuteranopia, green blindness, for
+    /// [`Renderer::set_color_matrix`], after Machado et al. 2009.
+    pub const DEUTERANOPIA: [[f32; 3]; 3] = [
+        [0.367322, 0.860646, -0.227968],
+        [0.280085, 0.672501, 0.047413],
+        [-0.011820, 0.042940, 0.968881],
+    ];
+
+    /// A color matrix simulating tritanopia, blue blindness, for
+    /// [`Renderer::set_color_matrix`], after Machado et al. 2009.
+    pub const TRITANOPIA: [[f32; 3]; 3] = [
+        [1.255528, -0.076749, -0.178779],
+        [-0.078411, 0.930809, 0.147602],
+        [0.004733, 0.691367, 0.303900],
+    ];
+
+    /// Creates a new renderer for the given [`ID3D11Device`] with the default
+    /// configuration.
+    ///
+    /// Use [`Renderer::builder`] to customize the renderer.
+    pub fn new(im_ctx: &mut imgui::Context, device: &ID3D11Device) -> Result<Self> {
+        RendererBuilder::new().build(im_ctx, device)
+    }
+
+    /// Creates a new renderer for the device that owns the given swapchain.
+    ///
+    /// Useful when the swapchain is the only object at hand, e.g. in overlays.
+    pub fn from_swapchain(im_ctx: &mut imgui::Context, swapchain: &IDXGISwapChain) -> Result<Self> {
+        let device = unsafe { swapchain.GetDevice::<ID3D11Device>()? };
+        Renderer::new(im_ctx, &device)
+    }
+
+    /// Creates a new renderer for a device of the `winapi` crate, for
+    /// applications built on it rather than the `windows` crate.
+    ///
+    /// The renderer takes its own reference to the device, the caller keeps
+    /// ownership of `device`.
+    #[cfg(feature = "winapi")]
+    pub fn from_winapi_device(
+        im_ctx: &mut imgui::Context,
+        device: &winapi::um::d3d11::ID3D11Device,
+    ) -> Result<Self> {
+        // Both crates represent a COM interface by the same pointer.
+        let raw = device as *const winapi::um::d3d11::ID3D11Device as *mut core::ffi::c_void;
+        unsafe { Renderer::from_raw_device(im_ctx, raw) }
+    }
+
+    /// Creates a new renderer for a raw `ID3D11Device*`, e.g. obtained by a
+    /// hooked function in an overlay.
+    ///
+    /// The renderer takes its own reference to the device, released when it
+    /// is dropped. The reference held by the caller is neither consumed nor
+    /// released, so the caller releases it as it would without the renderer.
     ///
     /// # Safety
     ///
-    /// `device` must be a valid [`ID3D11Device`] pointer.
+    /// `device_ptr` must point to a valid `ID3D11Device` the caller holds a
+    /// reference to for the duration of this call.
+    pub unsafe fn from_raw_device(
+        im_ctx: &mut imgui::Context,
+        device_ptr: *mut core::ffi::c_void,
+    ) -> Result<Self> {
+        // Wrapping the pointer without releasing it leaves the caller's
+        // reference untouched, while the renderer adds its own by cloning.
+        let device = mem::ManuallyDrop::new(ID3D11Device::from_raw(device_ptr));
+        Renderer::new(im_ctx, &device)
+    }
+
+    /// Creates a render target view for the backbuffer of the given swapchain.
+    pub fn create_backbuffer_rtv(
+        device: &ID3D11Device,
+        swapchain: &IDXGISwapChain,
+    ) -> Result<ID3D11RenderTargetView> {
+        unsafe {
+            let backbuffer: ID3D11Resource = swapchain.GetBuffer(0)?;
+            let mut render_target = None;
+            device.CreateRenderTargetView(&backbuffer, None, Some(&mut render_target))?;
+            Ok(render_target.unwrap())
+        }
+    }
+
+    /// Sets the [`Renderer::BACKEND_FLAGS`] on `io` again, e.g. after the
+    /// flags have been reset by the application.
+    pub fn configure_backend_flags(&self, io: &mut imgui::Io) {
+        io.backend_flags |= Self::BACKEND_FLAGS;
+    }
+
+    /// The bytecode of the embedded vertex shader, e.g. to create an input
+    /// layout for [`Renderer::set_vertex_shader`].
+    #[inline]
+    pub fn vertex_shader_bytecode() -> &'static [u8] {
+        VERTEX_SHADER
+    }
+
+    /// The bytecode of the embedded pixel shader used for
+    /// [`TargetFormat::Unorm`] targets.
+    #[inline]
+    pub fn pixel_shader_bytecode() -> &'static [u8] {
+        PIXEL_SHADER
+    }
+
+    /// The orthographic projection mapping the display area of `draw_data` to
+    /// clip space, laid out like [`Renderer::set_transform`] expects.
+    ///
+    /// This allows custom draws, e.g. from raw callbacks, to line up with
+    /// imgui's geometry. The depth, flip and transform set on a renderer are
+    /// applied on top of it, the depth defaulting to `0.5` like here.
+    pub fn projection_matrix(draw_data: &DrawData) -> [[f32; 4]; 4] {
+        let l = draw_data.display_pos[0];
+        let r = draw_data.display_pos[0] + draw_data.display_size[0];
+        let t = draw_data.display_pos[1];
+        let b = draw_data.display_pos[1] + draw_data.display_size[1];
+        [
+            [2.0 / (r - l), 0.0, 0.0, 0.0],
+            [0.0, 2.0 / (t - b), 0.0, 0.0],
+            [0.0, 0.0, 0.5, 0.0],
+            [(r + l) / (l - r), (t + b) / (b - t), 0.5, 1.0],
+        ]
+    }
+
+    /// Describes the adapter and feature level of the renderer's device, e.g.
+    /// for bug reports.
+    pub fn adapter_info(&self) -> Result<AdapterInfo> {
+        unsafe {
+            let adapter = self.device.cast::<IDXGIDevice>()?.GetAdapter()?;
+            let desc = adapter.GetDesc()?;
+            let name_len =
+                desc.Description.iter().position(|&c| c == 0).unwrap_or(desc.Description.len());
+            Ok(AdapterInfo {
+                name: String::from_utf16_lossy(&desc.Description[..name_len]),
+                vendor_id: desc.VendorId,
+                device_id: desc.DeviceId,
+                dedicated_video_memory: desc.DedicatedVideoMemory,
+                feature_level: self.device.GetFeatureLevel(),
+            })
+        }
+    }
+
+    /// Returns a [`RendererBuilder`] to configure a new renderer.
+    #[inline]
+    pub fn builder() -> RendererBuilder {
+        RendererBuilder::new()
+    }
+
+    /// Creates the shaders, buffers, states and font texture of the renderer
+    /// on `device`, replacing the current ones.
     ///
-    /// [`ID3D11Device`]: https://docs.rs/winapi/0.3/x86_64-pc-windows-msvc/winapi/um/d3d11/struct.ID3D11Device.html
-    pub fn new(im_ctx: &mut imgui::Context, device: &ID3D11Device) -> Result<Self> {
+    /// Together with [`Renderer::invalidate_device_objects`] this allows
+    /// recovering from a lost device without recreating the renderer. When
+    /// `device` differs from the current one, the renderer switches to its
+    /// immediate context.
+    pub fn create_device_objects(
+        &mut self,
+        im_ctx: &mut imgui::Context,
+        device: &ID3D11Device,
+    ) -> Result<()> {
         unsafe {
-            let (vertex_shader, input_layout, constant_buffer) =
-                Self::create_vertex_shader(device)?;
-            let pixel_shader = Self::create_pixel_shader(device)?;
-            let (blend_state, rasterizer_state, depth_stencil_state) =
-                Self::create_device_objects(device)?;
-            let (font_resource_view, font_sampler) =
-                Self::create_font_texture(im_ctx.fonts(), device)?;
-            let vertex_buffer = Self::create_vertex_buffer(device, 0)?;
-            let index_buffer = Self::create_index_buffer(device, 0)?;
-
-            let context = device.GetImmediateContext();
-
-            im_ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
-            let renderer_name = concat!("imgui_dx11_renderer@", env!("CARGO_PKG_VERSION"));
-            im_ctx.set_renderer_name(Some(renderer_name.to_string()));
+            let feature_level = device.GetFeatureLevel();
+            if feature_level.0 < D3D_FEATURE_LEVEL_10_0.0 {
+                return Err(RendererError::UnsupportedFeatureLevel(feature_level));
+            }
+            let immediate_context = device.GetImmediateContext()?;
+            let config = &self.config;
 
-            Ok(Renderer {
-                device: device.clone(),
-                context: context.unwrap(),
+            let (vertex_shader, input_layout) = Self::create_vertex_shader(device, VERTEX_SHADER)?;
+            let constant_buffer = Self::create_vertex_constant_buffer(device, config)?;
+            let unorm_pixel_shader = Self::create_pixel_shader(device, PIXEL_SHADER)?;
+            let srgb_pixel_shader = Self::create_pixel_shader(device, PIXEL_SHADER_SRGB)?;
+            let hdr_pixel_shader = Self::create_pixel_shader(device, PIXEL_SHADER_HDR)?;
+            let pixel_shader = if config.sdr_white_level.is_some() {
+                hdr_pixel_shader.clone()
+            } else if config.linear_blending {
+                srgb_pixel_shader.clone()
+            } else {
+                unorm_pixel_shader.clone()
+            };
+            let pixel_constant_buffer =
+                Self::create_pixel_constant_buffer(device, &self.pixel_constants)?;
+            let (blend_state, rasterizer_state, wireframe_rasterizer_state, depth_stencil_state) =
+                Self::create_states(device, config)?;
+            let (font_resource_view, font_sampler, font_texture_size) =
+                Self::create_font_texture(im_ctx.fonts(), device, &immediate_context, config)?;
+            let font_atlas_key = FontAtlasKey::new(im_ctx.fonts());
+            let texture_sampler = config
+                .texture_anisotropy
+                .map(|max_anisotropy| Self::create_texture_sampler(device, max_anisotropy))
+                .transpose()?;
+            let gpu_timer = if config.gpu_timing { Some(GpuTimer::new(device)?) } else { None };
+            let vertex_buffer =
+                Self::create_vertex_buffer(device, 0, self.vertex_headroom, self.vertex_stride)?;
+            let index_buffer = Self::create_index_buffer(device, 0, self.index_headroom)?;
+
+            if *device != self.device {
+                self.device = device.clone();
+                self.context = immediate_context;
+            }
+            self.objects = Some(DeviceObjects {
                 vertex_shader,
-                pixel_shader,
                 input_layout,
                 constant_buffer,
+                pixel_shader,
+                format_pixel_shaders: [unorm_pixel_shader, srgb_pixel_shader, hdr_pixel_shader],
+                pixel_constant_buffer,
                 blend_state,
                 rasterizer_state,
+                wireframe_rasterizer_state,
                 depth_stencil_state,
                 font_resource_view,
                 font_sampler,
+                font_texture_size,
+                font_atlas_key,
+                texture_sampler,
+                gpu_timer,
                 vertex_buffer,
                 index_buffer,
-                textures: Textures::new(),
-            })
+            });
+            self.pixel_constants_dirty = false;
+            self.last_mvp = None;
         }
+        Ok(())
+    }
+
+    /// Releases all objects the renderer created on its device, the shaders,
+    /// buffers, states and font texture, alongside the registered textures and
+    /// the custom shaders and states.
+    ///
+    /// This allows releasing them deterministically, e.g. before destroying
+    /// the device on shutdown or after it was lost. Rendering does nothing
+    /// until [`Renderer::create_device_objects`] is called.
+    pub fn invalidate_device_objects(&mut self) {
+        self.objects = None;
+        self.custom_vertex_shader = None;
+        self.custom_pixel_shader = None;
+        self.custom_blend_state = None;
+        self.custom_rasterizer_state = None;
+        self.clear_textures();
+    }
+
+    /// Replaces the sampler supplied via [`RendererBuilder::font_sampler`],
+    /// e.g. with one of the new device after the device was lost.
+    ///
+    /// Takes effect when the font texture is created next, usually by
+    /// [`Renderer::create_device_objects`]. `None` makes the renderer create
+    /// its own sampler again.
+    pub fn set_font_sampler(&mut self, sampler: Option<ID3D11SamplerState>) {
+        self.config.font_sampler = sampler;
+    }
+
+    /// Replaces the buffer supplied via
+    /// [`RendererBuilder::vertex_constant_buffer`], e.g. with one of the new
+    /// device after the device was lost.
+    ///
+    /// Takes effect when the device objects are created next by
+    /// [`Renderer::create_device_objects`]. `None` makes the renderer create
+    /// its own buffer again.
+    pub fn set_vertex_constant_buffer(&mut self, buffer: Option<ID3D11Buffer>) {
+        self.config.vertex_constant_buffer = buffer;
+    }
+
+    /// The objects created on the device, which must not have been invalidated.
+    fn objects(&self) -> &DeviceObjects {
+        self.objects.as_ref().expect("device objects have been invalidated")
     }
 
     /// The textures registry of this renderer.
@@ -109,133 +1082,1420 @@ impl Renderer {
         &self.textures
     }
 
-    /// Renders the given [`Ui`] with this renderer.
+    /// The view registered for `id`, if any.
     ///
-    /// Should the [`DrawData`] contain an invalid texture index the renderer
-    /// will return `DXGI_ERROR_INVALID_CALL` and immediately stop rendering.
+    /// Returns `None` for the font texture, which is not part of the registry,
+    /// use [`Renderer::font_texture_view`] instead.
+    pub fn texture(&self, id: TextureId) -> Option<&ID3D11ShaderResourceView> {
+        if id.id() == FONT_TEX_ID {
+            return None;
+        }
+        self.textures.get(id)
+    }
+
+    /// Registers `view` for `id`, returning the view previously registered
+    /// for it, if any.
     ///
-    /// [`Ui`]: https://docs.rs/imgui/*/imgui/struct.Ui.html
-    pub fn render(&mut self, draw_data: &DrawData) -> Result<()> {
-        if draw_data.display_size[0] <= 0.0 || draw_data.display_size[1] <= 0.0 {
-            return Ok(());
+    /// The id of a removed texture is registered again this way, and no longer
+    /// handed out to newly registered textures. The font texture slot is
+    /// reserved, `view` is dropped and `None` returned when `id` refers to it.
+    pub fn replace_texture(
+        &mut self,
+        id: TextureId,
+        view: ID3D11ShaderResourceView,
+    ) -> Option<ID3D11ShaderResourceView> {
+        if id.id() == FONT_TEX_ID {
+            log_warn!("refusing to replace the font texture");
+            return None;
         }
-        unsafe {
-            if self.vertex_buffer.len() < draw_data.total_vtx_count as usize {
-                self.vertex_buffer =
-                    Self::create_vertex_buffer(&self.device, draw_data.total_vtx_count as usize)?;
-            }
-            if self.index_buffer.len() < draw_data.total_idx_count as usize {
-                self.index_buffer =
-                    Self::create_index_buffer(&self.device, draw_data.total_idx_count as usize)?;
-            }
-            let _state_guard = StateBackup::backup(Some(self.context.clone()));
+        self.free_texture_ids.retain(|&free_id| free_id != id);
+        self.track_texture_id(id);
+        self.textures.replace(id, view)
+    }
 
-            self.write_buffers(draw_data)?;
-            self.setup_render_state(draw_data);
-            self.render_impl(draw_data)?;
+    /// Removes all textures from the textures registry, releasing their views.
+    ///
+    /// All previously issued [`TextureId`]s become invalid, except for the font
+    /// texture's which is not part of the registry. Outstanding
+    /// [`TextureHandle`]s don't affect the textures registered afterwards.
+    pub fn clear_textures(&mut self) {
+        self.textures = Textures::new();
+        self.dropped_textures.borrow_mut().clear();
+        self.free_texture_ids.clear();
+        // Ids are handed out from zero again.
+        for generation in &mut self.texture_generations {
+            *generation = generation.wrapping_add(1);
+        }
+        self.texture_id_bound = 0;
+    }
+
+    /// Removes the texture registered for `id`, returning its view.
+    ///
+    /// The id is reused by the next registered texture. A [`TextureHandle`]
+    /// of the removed texture doesn't affect the one reusing its id.
+    pub fn remove_texture(&mut self, id: TextureId) -> Option<ID3D11ShaderResourceView> {
+        let view = self.textures.remove(id)?;
+        if let Some(generation) = self.texture_generations.get_mut(id.id()) {
+            *generation = generation.wrapping_add(1);
+        }
+        self.free_texture_ids.push(id);
+        Some(view)
+    }
+
+    /// Inserts `view` into the registry, preferring ids of removed textures
+    /// over allocating new ones.
+    fn insert_texture(&mut self, view: ID3D11ShaderResourceView) -> TextureId {
+        let id = match self.free_texture_ids.pop() {
+            Some(id) => {
+                self.textures.replace(id, view);
+                id
+            },
+            None => self.textures.insert(view),
+        };
+        self.track_texture_id(id);
+        id
+    }
+
+    /// Extends the ids iterated and the generations tracked to cover `id`.
+    fn track_texture_id(&mut self, id: TextureId) {
+        self.texture_id_bound = self.texture_id_bound.max(id.id() + 1);
+        if self.texture_generations.len() < self.texture_id_bound {
+            self.texture_generations.resize(self.texture_id_bound, 0);
+        }
+    }
+
+    /// Iterates over the textures registered through the renderer alongside
+    /// their ids, excluding the font texture.
+    ///
+    /// As the registry can't be enumerated itself, textures inserted into
+    /// [`Renderer::textures_mut`] directly are not included.
+    pub fn iter_textures(&self) -> impl Iterator<Item = (TextureId, &ID3D11ShaderResourceView)> {
+        (0..self.texture_id_bound)
+            .map(TextureId::from)
+            .filter_map(move |id| Some((id, self.textures.get(id)?)))
+    }
+
+    /// Wraps the id of a texture just registered into a [`TextureHandle`].
+    ///
+    /// As the registry can't be borrowed by the handle, the removal is
+    /// deferred to the start of the next render call.
+    fn texture_handle(&self, id: TextureId) -> TextureHandle {
+        TextureHandle {
+            id,
+            generation: self.texture_generations[id.id()],
+            dropped_textures: Rc::downgrade(&self.dropped_textures),
+        }
+    }
+
+    /// Replaces the pixel shader used for all imgui draws, e.g. to tint or
+    /// color grade the whole UI.
+    ///
+    /// The shader receives the output of the built-in vertex shader:
+    ///
+    /// ```hlsl
+    /// struct PS_INPUT {
+    ///     float4 pos: SV_POSITION;
+    ///     float4 col: COLOR0;
+    ///     float2 uv: TEXCOORD0;
+    /// };
+    /// ```
+    ///
+    /// The texture of the current draw command is bound to `t0` and its
+    /// sampler to `s0`. To render imgui correctly the shader should sample
+    /// the texture at `uv` and multiply the result by `col`. The constant
+    /// buffer at `b0` starts with the `float4` set via [`Renderer::set_tint`].
+    pub fn set_pixel_shader(&mut self, shader: ID3D11PixelShader) {
+        self.custom_pixel_shader = Some(shader);
+    }
+
+    /// Restores the built-in pixel shader after a call to
+    /// [`Renderer::set_pixel_shader`].
+    pub fn reset_pixel_shader(&mut self) {
+        self.custom_pixel_shader = None;
+    }
+
+    /// Replaces the vertex shader and input layout used for all imgui draws.
+    ///
+    /// No validation happens at runtime, the input layout must match the
+    /// memory layout of [`DrawVert`]:
+    ///
+    /// | Semantic    | Format                       |
+    /// |-------------|------------------------------|
+    /// | `POSITION`  | `DXGI_FORMAT_R32G32_FLOAT`   |
+    /// | `TEXCOORD`  | `DXGI_FORMAT_R32G32_FLOAT`   |
+    /// | `COLOR`     | `DXGI_FORMAT_R8G8B8A8_UNORM` |
+    ///
+    /// The orthographic projection matrix is bound as a constant buffer to
+    /// slot `b0`, holding a single `float4x4`.
+    pub fn set_vertex_shader(
+        &mut self,
+        shader: ID3D11VertexShader,
+        input_layout: ID3D11InputLayout,
+    ) {
+        self.custom_vertex_shader = Some((shader, input_layout));
+    }
+
+    /// Restores the built-in vertex shader and input layout after a call to
+    /// [`Renderer::set_vertex_shader`].
+    pub fn reset_vertex_shader(&mut self) {
+        self.custom_vertex_shader = None;
+    }
+
+    /// Sets a transform that is applied to all imgui geometry after the
+    /// orthographic projection, e.g. to rotate or offset the whole UI.
+    ///
+    /// The matrix operates in clip space and is laid out like the projection
+    /// matrix, with each inner array being a column. Defaults to the identity.
+    pub fn set_transform(&mut self, transform: [[f32; 4]; 4]) {
+        self.transform = transform;
+    }
+
+    /// Sets the depth all imgui geometry is rendered at, e.g. `0.0` to keep
+    /// the UI in front of a scene when depth testing. Defaults to `0.5`.
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth;
+    }
+
+    /// Flips the projection vertically, for render targets with a bottom-left
+    /// origin like textures consumed by OpenGL-style pipelines. Disabled by
+    /// default.
+    pub fn set_flip_y(&mut self, flip_y: bool) {
+        self.flip_y = flip_y;
+    }
+
+    /// Moves the whole UI by `offset` pixels on the render target, e.g. to
+    /// composite imgui into a panel of a larger surface. The scissor rects are
+    /// moved along with it and stay within the moved viewport. Defaults to
+    /// `[0.0, 0.0]`.
+    pub fn set_viewport_offset(&mut self, offset: [f32; 2]) {
+        self.viewport_offset = offset;
+    }
+
+    /// Sets the viewport imgui is rendered into instead of the one covering the
+    /// display area from the origin of the render target, e.g. for split
+    /// screen or picture in picture UIs.
+    ///
+    /// The projection still maps the display area of the draw data onto the
+    /// viewport, so its size should be `display_size * framebuffer_scale` for
+    /// the UI not to be stretched, the scissor rects are stretched alike. Render
+    /// calls into a target, like [`Renderer::render_to_target`], keep covering
+    /// their whole target. Defaults to `None`.
+    pub fn set_render_viewport(&mut self, viewport: Option<D3D11_VIEWPORT>) {
+        self.render_viewport = viewport;
+    }
+
+    /// Restricts all imgui draws to `bounds` in render target pixels, by
+    /// intersecting them with every scissor rect.
+    ///
+    /// Together with [`Renderer::set_viewport_offset`] this keeps imgui from
+    /// drawing outside of the panel it is composited into. This has no effect
+    /// if the scissor test is disabled. Defaults to `None`.
+    pub fn set_scissor_bounds(&mut self, bounds: Option<RECT>) {
+        self.scissor_bounds = bounds;
+    }
+
+    /// Replaces the blend state used for all imgui draws, e.g. for additive
+    /// blending of glow overlays.
+    ///
+    /// The state is bound with a blend factor of zero and a sample mask of
+    /// `0xFFFFFFFF`.
+    pub fn set_blend_state(&mut self, state: ID3D11BlendState) {
+        self.custom_blend_state = Some(state);
+    }
+
+    /// Restores the blend state configured via [`RendererBuilder::blend_mode`]
+    /// after a call to [`Renderer::set_blend_state`].
+    pub fn reset_blend_state(&mut self) {
+        self.custom_blend_state = None;
+    }
+
+    /// Draws imgui's triangles as wireframes, e.g. to inspect the geometry of
+    /// custom draw lists. Clipping stays in place. A rasterizer state set via
+    /// [`Renderer::set_rasterizer_state`] takes precedence. Disabled by
+    /// default.
+    pub fn set_wireframe(&mut self, enable: bool) {
+        self.wireframe = enable;
+    }
+
+    /// Replaces the rasterizer state used for all imgui draws, e.g. to enable
+    /// multisampling.
+    ///
+    /// **`ScissorEnable` must be set for imgui to clip its draws**, windows and
+    /// scrolled contents draw outside of their bounds otherwise. Scissor rects
+    /// are only set up if the scissor test is enabled via
+    /// [`RendererBuilder::scissor_test`].
+    pub fn set_rasterizer_state(&mut self, state: ID3D11RasterizerState) {
+        self.custom_rasterizer_state = Some(state);
+    }
+
+    /// Restores the built-in rasterizer state after a call to
+    /// [`Renderer::set_rasterizer_state`].
+    pub fn reset_rasterizer_state(&mut self) {
+        self.custom_rasterizer_state = None;
+    }
+
+    /// Sets a callback invoked whenever a draw list requests a reset of the
+    /// render state, after imgui's state has been set up again.
+    ///
+    /// This allows reasserting state of the application in the middle of a
+    /// draw list, e.g. render targets a raw callback changed. Defaults to
+    /// `None`.
+    pub fn set_on_reset_render_state(
+        &mut self,
+        callback: Option<Box<dyn Fn(&ID3D11DeviceContext)>>,
+    ) {
+        self.on_reset_render_state = callback.map(Callback);
+    }
+
+    /// Sets a callback invoked before each draw list is rendered with its index
+    /// in the draw data, e.g. to begin a debug marker via
+    /// `ID3DUserDefinedAnnotation::BeginEvent`.
+    ///
+    /// Not invoked by [`Renderer::render_draw_list`]. Defaults to `None`.
+    pub fn set_before_draw_list(
+        &mut self,
+        callback: Option<Box<dyn Fn(usize, &ID3D11DeviceContext)>>,
+    ) {
+        self.before_draw_list = callback.map(Callback);
+    }
+
+    /// Sets a callback invoked after each draw list is rendered with its index
+    /// in the draw data, e.g. to end a debug marker begun in the callback set
+    /// via [`Renderer::set_before_draw_list`]. Defaults to `None`.
+    pub fn set_after_draw_list(
+        &mut self,
+        callback: Option<Box<dyn Fn(usize, &ID3D11DeviceContext)>>,
+    ) {
+        self.after_draw_list = callback.map(Callback);
+    }
+
+    /// Sets a color all imgui output is multiplied with, e.g. to fade the whole
+    /// UI in and out or to tint it. Defaults to opaque white.
+    pub fn set_tint(&mut self, tint: [f32; 4]) {
+        self.pixel_constants.tint = tint;
+        self.pixel_constants_dirty = true;
+    }
+
+    /// Sets the gamma imgui's output is corrected with, raising its colors to
+    /// the power of `1.0 / gamma`, e.g. to brighten the UI for accessibility.
+    /// Defaults to `1.0`.
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.pixel_constants.gamma = gamma;
+        self.pixel_constants_dirty = true;
+    }
+
+    /// Sets a factor imgui's output colors are scaled by after the gamma
+    /// correction, see [`Renderer::set_gamma`]. Defaults to `1.0`.
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.pixel_constants.brightness = brightness;
+        self.pixel_constants_dirty = true;
+    }
+
+    /// Sets a matrix imgui's output colors are transformed with, e.g. one of
+    /// [`Renderer::PROTANOPIA`], [`Renderer::DEUTERANOPIA`] and
+    /// [`Renderer::TRITANOPIA`] to validate a color scheme for color blind
+    /// users.
+    ///
+    /// Each inner array is a row, the output red channel being the dot product
+    /// of the first row with the input color. The matrix is applied last,
+    /// before the output is linearized for sRGB and HDR targets. Defaults to
+    /// the identity.
+    pub fn set_color_matrix(&mut self, matrix: [[f32; 3]; 3]) {
+        self.pixel_constants.color_matrix = matrix.map(|[r, g, b]| [r, g, b, 0.0]);
+        self.pixel_constants_dirty = true;
+    }
+
+    /// Sets the brightness of SDR white in scRGB units, where `1.0`
+    /// corresponds to 80 nits.
+    ///
+    /// This only has an effect if the renderer was created with
+    /// [`RendererBuilder::hdr_output`].
+    pub fn set_sdr_white_level(&mut self, sdr_white_level: f32) {
+        self.pixel_constants.sdr_white_level = sdr_white_level;
+        self.pixel_constants_dirty = true;
+    }
+
+    /// Recreates the built-in vertex shader, input layout and pixel shader from
+    /// the given compiled shader bytecode, e.g. `.cso` files loaded at runtime.
+    ///
+    /// The shaders must follow the same contract as the embedded ones, see
+    /// [`Renderer::set_vertex_shader`] and [`Renderer::set_pixel_shader`]. If
+    /// creating any of them fails the previous shaders are kept. Recreating the
+    /// device objects restores the embedded shaders.
+    pub fn reload_shaders(&mut self, vs_bytecode: &[u8], ps_bytecode: &[u8]) -> Result<()> {
+        let Some(objects) = &mut self.objects else {
+            return Ok(());
+        };
+        unsafe {
+            let (vertex_shader, input_layout) =
+                Self::create_vertex_shader(&self.device, vs_bytecode)?;
+            let pixel_shader = Self::create_pixel_shader(&self.device, ps_bytecode)?;
+            objects.vertex_shader = vertex_shader;
+            objects.input_layout = input_layout;
+            objects.pixel_shader = pixel_shader;
+        }
+        Ok(())
+    }
+
+    /// Finishes recording the commands of a deferred context into a command
+    /// list, which can then be executed on the immediate context via
+    /// `ExecuteCommandList`.
+    ///
+    /// This requires the renderer to be created with a deferred context via
+    /// [`RendererBuilder::device_context`], it fails otherwise.
+    pub fn finish_command_list(&self) -> Result<ID3D11CommandList> {
+        unsafe {
+            let mut command_list = None;
+            self.context.FinishCommandList(BOOL::from(false), Some(&mut command_list))?;
+            Ok(command_list.unwrap())
+        }
+    }
+
+    /// The number of vertices and indices the buffers are allocated with on top
+    /// of what a frame needs, see [`RendererBuilder::buffer_headroom`].
+    #[inline]
+    pub fn buffer_headroom(&self) -> (usize, usize) {
+        (self.vertex_headroom, self.index_headroom)
+    }
+
+    /// Grows the vertex and index buffers to hold at least the given number of
+    /// vertices and indices, plus the headroom. Never shrinks them.
+    ///
+    /// The buffers are grown lazily when rendering otherwise, this allows
+    /// avoiding the reallocation hitch on the first frames.
+    pub fn reserve(&mut self, vtx_count: usize, idx_count: usize) -> Result<()> {
+        if let Some((max_vertices, max_indices)) = self.config.buffer_limit {
+            if vtx_count > max_vertices {
+                log_warn!("{} vertices exceed the limit of {}", vtx_count, max_vertices);
+                return Err(RendererError::BufferTooLarge { len: vtx_count });
+            }
+            if idx_count > max_indices {
+                log_warn!("{} indices exceed the limit of {}", idx_count, max_indices);
+                return Err(RendererError::BufferTooLarge { len: idx_count });
+            }
+        }
+        let Some(objects) = &mut self.objects else {
+            return Ok(());
+        };
+        unsafe {
+            if objects.vertex_buffer.len() < vtx_count {
+                log_debug!(
+                    "growing vertex buffer from {} to {} vertices",
+                    objects.vertex_buffer.len(),
+                    vtx_count
+                );
+                objects.vertex_buffer = Self::create_vertex_buffer(
+                    &self.device,
+                    vtx_count,
+                    self.vertex_headroom,
+                    self.vertex_stride,
+                )?;
+            }
+            if objects.index_buffer.len() < idx_count {
+                log_debug!(
+                    "growing index buffer from {} to {} indices",
+                    objects.index_buffer.len(),
+                    idx_count
+                );
+                objects.index_buffer =
+                    Self::create_index_buffer(&self.device, idx_count, self.index_headroom)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Statistics about the geometry submitted by the last render call.
+    #[inline]
+    pub fn last_stats(&self) -> RenderStats {
+        self.last_stats
+    }
+
+    /// The number of draw lists rendered by the last render call.
+    ///
+    /// This is zero if the call was skipped, e.g. because the draw data was
+    /// empty or the window minimized.
+    #[inline]
+    pub fn last_draw_list_count(&self) -> usize {
+        self.last_draw_list_count
+    }
+
+    /// The GPU time in milliseconds of an earlier render call, see
+    /// [`RendererBuilder::gpu_timing`].
+    ///
+    /// The measurement lags a few frames behind. Returns `None` if timing is
+    /// disabled, no measurement is available yet or the timestamps of the
+    /// measured call were unreliable, e.g. because the GPU changed its clock.
+    #[inline]
+    pub fn last_gpu_time(&self) -> Option<f32> {
+        self.objects.as_ref()?.gpu_timer.as_ref()?.last_time
+    }
+
+    /// Rebuilds the font texture if the font atlas changed since it was
+    /// built, e.g. because fonts were added.
+    ///
+    /// The atlas is owned by the imgui context, which the draw data borrows
+    /// while rendering, and the renderer keeps no handle to it. Looking it up
+    /// through imgui's globals instead would tie rendering to whichever
+    /// context is current on the calling thread, breaking renderers driven
+    /// from another thread. The atlas is therefore passed in explicitly: call
+    /// this before `Context::new_frame`, which requires a built atlas, after
+    /// adding fonts or building the atlas yourself. It is cheap if nothing
+    /// changed.
+    pub fn update_font_texture(&mut self, fonts: &mut imgui::FontAtlas) -> Result<()> {
+        let Some(objects) = &mut self.objects else {
+            return Ok(());
+        };
+        if fonts.is_built() && objects.font_atlas_key == FontAtlasKey::new(fonts) {
+            return Ok(());
+        }
+        unsafe {
+            let immediate_context = self.device.GetImmediateContext()?;
+            let (font_resource_view, font_sampler, font_texture_size) =
+                Self::create_font_texture(fonts, &self.device, &immediate_context, &self.config)?;
+            objects.font_resource_view = font_resource_view;
+            objects.font_sampler = font_sampler;
+            objects.font_texture_size = font_texture_size;
+            objects.font_atlas_key = FontAtlasKey::new(fonts);
+        }
+        Ok(())
+    }
+
+    /// The shader resource view of the font atlas texture.
+    ///
+    /// The view is owned by the renderer, callers may bind it for their own
+    /// draws but must not release it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device objects have been invalidated.
+    #[inline]
+    pub fn font_texture_view(&self) -> &ID3D11ShaderResourceView {
+        &self.objects().font_resource_view
+    }
+
+    /// The width and height of the font atlas texture in pixels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device objects have been invalidated.
+    #[inline]
+    pub fn font_texture_size(&self) -> [u32; 2] {
+        self.objects().font_texture_size
+    }
+
+    /// The blend state configured via [`RendererBuilder::blend_mode`], e.g. to
+    /// composite custom draws exactly like imgui's.
+    ///
+    /// A blend state set via [`Renderer::set_blend_state`] is not returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device objects have been invalidated.
+    #[inline]
+    pub fn blend_state(&self) -> &ID3D11BlendState {
+        &self.objects().blend_state
+    }
+
+    /// The rasterizer state imgui is drawn with.
+    ///
+    /// A rasterizer state set via [`Renderer::set_rasterizer_state`] is not
+    /// returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device objects have been invalidated.
+    #[inline]
+    pub fn rasterizer_state(&self) -> &ID3D11RasterizerState {
+        &self.objects().rasterizer_state
+    }
+
+    /// The depth stencil state imgui is drawn with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device objects have been invalidated.
+    #[inline]
+    pub fn depth_stencil_state(&self) -> &ID3D11DepthStencilState {
+        &self.objects().depth_stencil_state
+    }
+
+    /// Creates a shader resource view for the given texture and registers it
+    /// in the textures registry.
+    ///
+    /// The view covers all mip levels of the texture and uses its format.
+    /// Textures with a typeless format are rejected with
+    /// [`RendererError::TypelessFormat`], as their view format can't be
+    /// inferred.
+    pub fn register_texture2d(&mut self, texture: &ID3D11Texture2D) -> Result<TextureId> {
+        unsafe {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            texture.GetDesc(&mut desc);
+            if is_typeless_format(desc.Format) {
+                return Err(RendererError::TypelessFormat(desc.Format));
+            }
+
+            let mut srv_desc = D3D11_SHADER_RESOURCE_VIEW_DESC {
+                Format: desc.Format,
+                ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
+                ..Default::default()
+            };
+            srv_desc.Anonymous.Texture2D.MipLevels = desc.MipLevels;
+            srv_desc.Anonymous.Texture2D.MostDetailedMip = 0;
+            let mut uninit_texture_view = None;
+            self.device.CreateShaderResourceView(
+                texture,
+                Some(&srv_desc),
+                Some(&mut uninit_texture_view),
+            )?;
+            Ok(self.insert_texture(uninit_texture_view.unwrap()))
+        }
+    }
+
+    /// Like [`Renderer::register_texture2d`], but returns a [`TextureHandle`]
+    /// that removes the texture from the registry once dropped.
+    pub fn register_texture2d_owned(&mut self, texture: &ID3D11Texture2D) -> Result<TextureHandle> {
+        let id = self.register_texture2d(texture)?;
+        Ok(self.texture_handle(id))
+    }
+
+    /// Uploads the given RGBA8 pixel data as a new texture and registers it in
+    /// the textures registry.
+    ///
+    /// `data` must contain exactly `width * height * 4` bytes, otherwise
+    /// [`RendererError::TextureDataSize`] is returned. Dimensions above the
+    /// device's limit, 16384 from feature level 11.0 and 8192 below, return
+    /// [`RendererError::TextureTooLarge`].
+    pub fn register_rgba8_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<TextureId> {
+        unsafe {
+            let view = Self::create_texture_view(
+                &self.device,
+                width,
+                height,
+                DXGI_FORMAT_R8G8B8A8_UNORM,
+                data,
+            )?;
+            Ok(self.insert_texture(view))
+        }
+    }
+
+    /// Like [`Renderer::register_rgba8_texture`], but returns a
+    /// [`TextureHandle`] that removes the texture from the registry once
+    /// dropped.
+    pub fn register_rgba8_texture_owned(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<TextureHandle> {
+        let id = self.register_rgba8_texture(width, height, data)?;
+        Ok(self.texture_handle(id))
+    }
+
+    /// Uploads the given BGRA8 pixel data as a new texture and registers it in
+    /// the textures registry, avoiding a conversion for decoders and surfaces
+    /// producing BGRA.
+    ///
+    /// `data` must contain exactly `width * height * 4` bytes, otherwise
+    /// [`RendererError::TextureDataSize`] is returned. The font texture is
+    /// always RGBA8.
+    pub fn register_bgra8_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<TextureId> {
+        unsafe {
+            let view = Self::create_texture_view(
+                &self.device,
+                width,
+                height,
+                DXGI_FORMAT_B8G8R8A8_UNORM,
+                data,
+            )?;
+            Ok(self.insert_texture(view))
+        }
+    }
+
+    /// Like [`Renderer::register_bgra8_texture`], but returns a
+    /// [`TextureHandle`] that removes the texture from the registry once
+    /// dropped.
+    pub fn register_bgra8_texture_owned(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<TextureHandle> {
+        let id = self.register_bgra8_texture(width, height, data)?;
+        Ok(self.texture_handle(id))
+    }
+
+    /// Uploads the given block compressed data as a new texture and registers
+    /// it in the textures registry, saving memory for large images.
+    ///
+    /// `mip_offsets` holds the offset into `data` of each mip level, starting
+    /// with the full resolution one, and an empty slice uploads a single level
+    /// at the start of `data`. Each level consists of its 4x4 blocks row by
+    /// row, levels smaller than a block taking up a whole one.
+    ///
+    /// `width` and `height` must be multiples of 4, otherwise
+    /// [`RendererError::UnalignedTextureSize`] is returned. A level exceeding
+    /// `data` returns [`RendererError::TextureDataSize`], and more levels than
+    /// a full mip chain has return [`RendererError::TooManyMipLevels`].
+    pub fn register_compressed_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: DxgiCompressedFormat,
+        data: &[u8],
+        mip_offsets: &[usize],
+    ) -> Result<TextureId> {
+        if width % 4 != 0 || height % 4 != 0 {
+            return Err(RendererError::UnalignedTextureSize { width, height });
+        }
+        let mip_offsets = if mip_offsets.is_empty() { &[0][..] } else { mip_offsets };
+        let max_levels = 32 - width.max(height).leading_zeros();
+        if mip_offsets.len() > max_levels as usize {
+            return Err(RendererError::TooManyMipLevels {
+                levels: mip_offsets.len(),
+                max: max_levels,
+            });
+        }
+        let sub_resources = mip_offsets
+            .iter()
+            .enumerate()
+            .map(|(level, &offset)| {
+                let blocks_wide = (((width >> level) as usize + 3) / 4).max(1);
+                let blocks_high = (((height >> level) as usize + 3) / 4).max(1);
+                let pitch = blocks_wide * format.block_size();
+                // An offset this large can't be satisfied by any `data`.
+                let expected = pitch
+                    .checked_mul(blocks_high)
+                    .and_then(|size| size.checked_add(offset))
+                    .unwrap_or(usize::MAX);
+                if data.len() < expected {
+                    return Err(RendererError::TextureDataSize { expected, actual: data.len() });
+                }
+                Ok(D3D11_SUBRESOURCE_DATA {
+                    pSysMem: data[offset..].as_ptr().cast(),
+                    SysMemPitch: pitch as u32,
+                    SysMemSlicePitch: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        unsafe {
+            Self::check_texture_size(&self.device, width, height)?;
+            let desc = D3D11_TEXTURE2D_DESC {
+                Width: width,
+                Height: height,
+                MipLevels: sub_resources.len() as u32,
+                ArraySize: 1,
+                Format: format.to_dxgi(),
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                Usage: D3D11_USAGE_IMMUTABLE,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                ..Default::default()
+            };
+            let mut uninit_texture = None;
+            self.device.CreateTexture2D(
+                &desc,
+                Some(sub_resources.as_ptr()),
+                Some(&mut uninit_texture),
+            )?;
+            let texture = uninit_texture.unwrap();
+
+            let mut uninit_texture_view = None;
+            self.device.CreateShaderResourceView(&texture, None, Some(&mut uninit_texture_view))?;
+            Ok(self.insert_texture(uninit_texture_view.unwrap()))
+        }
+    }
+
+    /// Like [`Renderer::register_compressed_texture`], but returns a
+    /// [`TextureHandle`] that removes the texture from the registry once
+    /// dropped.
+    pub fn register_compressed_texture_owned(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: DxgiCompressedFormat,
+        data: &[u8],
+        mip_offsets: &[usize],
+    ) -> Result<TextureHandle> {
+        let id = self.register_compressed_texture(width, height, format, data, mip_offsets)?;
+        Ok(self.texture_handle(id))
+    }
+
+    /// Decodes the image file at `path` and registers it in the textures
+    /// registry.
+    ///
+    /// Returns the id of the texture alongside its dimensions, ready to be
+    /// passed to an `Image` widget.
+    #[cfg(feature = "image")]
+    pub fn register_texture_from_path(&mut self, path: &str) -> Result<(TextureId, [f32; 2])> {
+        let image = image::open(path).map_err(RendererError::Image)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        let texture_id = self.register_rgba8_texture(width, height, image.as_raw())?;
+        Ok((texture_id, [width as f32, height as f32]))
+    }
+
+    /// Binds `rtv` and `dsv` as the render targets and clears them, the color
+    /// to `clear_color` and the depth to 1.0.
+    ///
+    /// Unlike [`Renderer::render`] the bound render targets are left in place,
+    /// so this is meant to be called at the start of a frame.
+    pub fn begin_frame(
+        &self,
+        rtv: &ID3D11RenderTargetView,
+        dsv: Option<&ID3D11DepthStencilView>,
+        clear_color: [f32; 4],
+    ) {
+        unsafe {
+            self.context.OMSetRenderTargets(Some(slice::from_ref(rtv)), dsv);
+            self.context.ClearRenderTargetView(rtv, clear_color.as_ptr());
+            if let Some(dsv) = dsv {
+                self.context.ClearDepthStencilView(dsv, D3D11_CLEAR_DEPTH.0 as u32, 1.0, 0);
+            }
+        }
+    }
+
+    /// Renders the given [`Ui`] with this renderer.
+    ///
+    /// Should the [`DrawData`] contain an invalid texture index the renderer
+    /// will return [`RendererError::InvalidTextureId`] and immediately stop
+    /// rendering.
+    ///
+    /// Draw data without any geometry or visible area returns `Ok(())` without
+    /// drawing anything. imgui produces it for every frame without visible
+    /// windows and while minimized, so it isn't treated as an error.
+    ///
+    /// [`Ui`]: https://docs.rs/imgui/*/imgui/struct.Ui.html
+    pub fn render(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.begin_render();
+        if !self.should_render(draw_data) {
+            return Ok(());
+        }
+        unsafe {
+            self.grow_buffers(draw_data)?;
+            let viewport = self.draw_data_viewport(draw_data);
+            self.with_state_backup(|renderer| renderer.draw(draw_data, &viewport))
+        }
+    }
+
+    /// Backs up the state of the device context once for several render calls,
+    /// e.g. for a main viewport and a number of overlays.
+    ///
+    /// The state is restored when the returned session is dropped. Calling
+    /// [`Renderer::render`] is equivalent to rendering once in a session.
+    pub fn begin(&mut self) -> RenderSession<'_> {
+        unsafe { self.state_backup.save(Some(self.context.clone())) };
+        RenderSession { renderer: self }
+    }
+
+    /// Renders the given [`DrawData`] like [`Renderer::render`], writing the
+    /// colors as appropriate for a render target of the given format.
+    ///
+    /// This allows rendering into targets of different formats with the same
+    /// renderer, e.g. an offscreen HDR texture and an sRGB backbuffer. A pixel
+    /// shader set via [`Renderer::set_pixel_shader`] takes precedence.
+    pub fn render_with_format(&mut self, draw_data: &DrawData, format: TargetFormat) -> Result<()> {
+        self.target_format = Some(format);
+        let result = self.render(draw_data);
+        self.target_format = None;
+        result
+    }
+
+    /// Renders a single draw list of `draw_data`, e.g. to interleave custom
+    /// draws between the draw lists of imgui.
+    ///
+    /// The projection is set up from `draw_data` like in [`Renderer::render`],
+    /// and the state of the device context is backed up and restored around
+    /// each call.
+    pub fn render_draw_list(&mut self, draw_data: &DrawData, draw_list: &DrawList) -> Result<()> {
+        self.begin_render();
+        let vtx_count = draw_list.vtx_buffer().len();
+        let idx_count = draw_list.idx_buffer().len();
+        if !self.should_render(draw_data) || vtx_count == 0 {
+            return Ok(());
+        }
+        self.reserve(vtx_count, idx_count)?;
+        let viewport = self.draw_data_viewport(draw_data);
+        unsafe {
+            self.with_state_backup(|renderer| {
+                renderer.write_geometry(iter::once(draw_list), vtx_count, idx_count)?;
+                renderer.write_projection(draw_data)?;
+                renderer.write_pixel_constants();
+                renderer.setup_render_state(&renderer.context, &viewport);
+                let mut state = DrawState::new(vtx_count, idx_count);
+                renderer.render_draw_list_impl(
+                    &renderer.context,
+                    draw_list,
+                    0,
+                    0,
+                    &mut state,
+                    draw_data,
+                    &viewport,
+                )?;
+                renderer.last_stats = state.stats;
+                renderer.last_draw_list_count = 1;
+                Ok(())
+            })
+        }
+    }
+
+    /// Renders the given [`DrawData`] without backing up and restoring the
+    /// state of the device context.
+    ///
+    /// **The device context is left with imgui's rendering state bound after
+    /// this returns**, including its shaders, buffers, blend, depth and
+    /// rasterizer states, viewport and scissor rect. Only use this if you own
+    /// the whole context and set up all the state you need afterwards yourself.
+    pub fn render_no_backup(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.begin_render();
+        if !self.should_render(draw_data) {
+            return Ok(());
+        }
+        unsafe {
+            self.grow_buffers(draw_data)?;
+            let viewport = self.draw_data_viewport(draw_data);
+            self.draw(draw_data, &viewport)
+        }
+    }
+
+    /// Renders the given [`DrawData`] into `rtv`, clearing it with `clear`
+    /// first if given.
+    ///
+    /// The viewport is set up to cover the whole render target. The previously
+    /// bound render targets are restored afterwards, like the rest of the
+    /// rendering state.
+    pub fn render_to_target(
+        &mut self,
+        draw_data: &DrawData,
+        rtv: &ID3D11RenderTargetView,
+        clear: Option<[f32; 4]>,
+    ) -> Result<()> {
+        self.begin_render();
+        unsafe {
+            self.with_state_backup(|renderer| {
+                renderer.context.OMSetRenderTargets(Some(slice::from_ref(rtv)), None);
+                if let Some(color) = clear {
+                    renderer.context.ClearRenderTargetView(rtv, color.as_ptr());
+                }
+                if !renderer.should_render(draw_data) {
+                    return Ok(());
+                }
+
+                renderer.grow_buffers(draw_data)?;
+                renderer.draw(draw_data, &Self::render_target_viewport(rtv)?)
+            })
+        }
+    }
+
+    /// Renders the given [`DrawData`] into a new `width` x `height` texture
+    /// cleared to transparent black, e.g. for thumbnails shown via an image
+    /// widget.
+    ///
+    /// The display area of the draw data is stretched over the whole texture.
+    /// The returned view is owned by the caller and not registered with the
+    /// renderer. Dimensions above the device's limit return
+    /// [`RendererError::TextureTooLarge`].
+    pub fn render_to_texture(
+        &mut self,
+        draw_data: &DrawData,
+        width: u32,
+        height: u32,
+    ) -> Result<ID3D11ShaderResourceView> {
+        unsafe {
+            Self::check_texture_size(&self.device, width, height)?;
+            let desc = D3D11_TEXTURE2D_DESC {
+                Width: width,
+                Height: height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+                ..Default::default()
+            };
+            let mut uninit_texture = None;
+            self.device.CreateTexture2D(&desc, None, Some(&mut uninit_texture))?;
+            let texture = uninit_texture.unwrap();
+            set_debug_name(&texture, "imgui-dx11 render texture");
+
+            let mut uninit_render_target = None;
+            self.device.CreateRenderTargetView(&texture, None, Some(&mut uninit_render_target))?;
+            let mut uninit_texture_view = None;
+            self.device.CreateShaderResourceView(&texture, None, Some(&mut uninit_texture_view))?;
+
+            // The texture and its views are released on failure when dropped.
+            self.render_to_target(
+                draw_data,
+                &uninit_render_target.unwrap(),
+                Some([0.0, 0.0, 0.0, 0.0]),
+            )?;
+            Ok(uninit_texture_view.unwrap())
+        }
+    }
+
+    /// Creates a `width` x `height` color target and a matching depth stencil
+    /// target, e.g. for UIs mixing in 3D content via raw callbacks.
+    ///
+    /// The color texture is `DXGI_FORMAT_R8G8B8A8_UNORM` and can also be bound
+    /// as a shader resource, the depth texture is
+    /// `DXGI_FORMAT_D24_UNORM_S8_UINT`. Bind both via [`Renderer::begin_frame`]
+    /// before rendering. Dimensions above the device's limit return
+    /// [`RendererError::TextureTooLarge`].
+    pub fn create_offscreen_targets(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<(ID3D11RenderTargetView, ID3D11DepthStencilView)> {
+        unsafe {
+            Self::check_texture_size(&self.device, width, height)?;
+            let mut desc = D3D11_TEXTURE2D_DESC {
+                Width: width,
+                Height: height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+                ..Default::default()
+            };
+            let mut uninit_texture = None;
+            self.device.CreateTexture2D(&desc, None, Some(&mut uninit_texture))?;
+            let texture = uninit_texture.unwrap();
+            set_debug_name(&texture, "imgui-dx11 offscreen color texture");
+            let mut uninit_render_target = None;
+            self.device.CreateRenderTargetView(&texture, None, Some(&mut uninit_render_target))?;
+
+            desc.Format = DXGI_FORMAT_D24_UNORM_S8_UINT;
+            desc.BindFlags = D3D11_BIND_DEPTH_STENCIL;
+            let mut uninit_depth_texture = None;
+            self.device.CreateTexture2D(&desc, None, Some(&mut uninit_depth_texture))?;
+            let depth_texture = uninit_depth_texture.unwrap();
+            set_debug_name(&depth_texture, "imgui-dx11 offscreen depth texture");
+            let mut uninit_depth_stencil = None;
+            self.device.CreateDepthStencilView(
+                &depth_texture,
+                None,
+                Some(&mut uninit_depth_stencil),
+            )?;
+
+            Ok((uninit_render_target.unwrap(), uninit_depth_stencil.unwrap()))
+        }
+    }
+
+    unsafe fn draw(&mut self, draw_data: &DrawData, viewport: &D3D11_VIEWPORT) -> Result<()> {
+        self.with_gpu_timer(|renderer| {
+            renderer.write_buffers(draw_data)?;
+            renderer.write_pixel_constants();
+            renderer.setup_render_state(&renderer.context, viewport);
+            renderer.last_stats = renderer.render_impl(&renderer.context, draw_data, viewport)?;
+            renderer.last_draw_list_count = draw_data.draw_lists_count();
+            Ok(())
+        })
+    }
+
+    /// Runs `f` between the timestamp queries of the GPU timer, if enabled.
+    unsafe fn with_gpu_timer<R>(&mut self, f: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        // Query data can only be read back on the immediate context.
+        let timed = self.context.GetType() == D3D11_DEVICE_CONTEXT_IMMEDIATE;
+        if let Some(timer) = self.objects.as_mut().and_then(|objects| objects.gpu_timer.as_mut()) {
+            if timed {
+                timer.begin(&self.context);
+            }
+        }
+        let result = f(self);
+        if let Some(timer) = self.objects.as_mut().and_then(|objects| objects.gpu_timer.as_mut()) {
+            if timed {
+                timer.end(&self.context);
+            }
+        }
+        result
+    }
+
+    /// Runs `f` with the state of the device context backed up, restoring it
+    /// afterwards even if `f` fails.
+    unsafe fn with_state_backup<R>(&mut self, f: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        let mut state_backup = mem::take(&mut self.state_backup);
+        let result = {
+            let _state_guard = state_backup.backup(Some(self.context.clone()));
+            f(self)
+        };
+        self.state_backup = state_backup;
+        result
+    }
+
+    /// Resets the statistics and removes the textures whose handles were
+    /// dropped since the last render call.
+    fn begin_render(&mut self) {
+        self.last_stats = RenderStats::default();
+        self.last_draw_list_count = 0;
+        let dropped_textures = mem::take(&mut *self.dropped_textures.borrow_mut());
+        for (id, generation) in dropped_textures {
+            if self.texture_generations.get(id.id()) == Some(&generation) {
+                self.remove_texture(id);
+            }
+        }
+    }
+
+    /// Whether the device objects are present and the draw data has a visible
+    /// area and any geometry at all.
+    ///
+    /// Empty draw data is skipped entirely, as mapping zero-sized buffer ranges
+    /// isn't meaningful. A non-positive framebuffer scale, as reported by some
+    /// platforms while minimized, would collapse the viewport and scissor rects.
+    fn should_render(&self, draw_data: &DrawData) -> bool {
+        self.objects.is_some()
+            && draw_data.display_size[0] > 0.0
+            && draw_data.display_size[1] > 0.0
+            && draw_data.framebuffer_scale[0] > 0.0
+            && draw_data.framebuffer_scale[1] > 0.0
+            && draw_data.total_vtx_count > 0
+    }
+
+    fn grow_buffers(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.reserve(draw_data.total_vtx_count as usize, draw_data.total_idx_count as usize)
+    }
+
+    fn draw_data_viewport(&self, draw_data: &DrawData) -> D3D11_VIEWPORT {
+        if let Some(viewport) = self.render_viewport {
+            return viewport;
+        }
+        D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: draw_data.display_size[0] * draw_data.framebuffer_scale[0],
+            Height: draw_data.display_size[1] * draw_data.framebuffer_scale[1],
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        }
+    }
+
+    unsafe fn render_target_viewport(rtv: &ID3D11RenderTargetView) -> Result<D3D11_VIEWPORT> {
+        let mut resource = None;
+        rtv.GetResource(&mut resource);
+        let texture = resource.unwrap().cast::<ID3D11Texture2D>()?;
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        texture.GetDesc(&mut desc);
+        Ok(D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: desc.Width as f32,
+            Height: desc.Height as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        })
+    }
+
+    unsafe fn render_impl(
+        &self,
+        backend: &impl RenderBackend,
+        draw_data: &DrawData,
+        viewport: &D3D11_VIEWPORT,
+    ) -> Result<RenderStats> {
+        // The offsets count vertices and indices into the buffers, whose byte
+        // width is limited to `u32`. With vertices larger than a byte the vertex
+        // offset therefore always fits the `i32` base vertex of `DrawIndexed`,
+        // including the vertex offsets of the commands within a draw list.
+        let mut vertex_offset: usize = 0;
+        let mut index_offset: usize = 0;
+        let mut state =
+            DrawState::new(draw_data.total_vtx_count as usize, draw_data.total_idx_count as usize);
+        for (index, draw_list) in draw_data.draw_lists().enumerate() {
+            if let Some(Callback(callback)) = &self.before_draw_list {
+                callback(index, &self.context);
+            }
+            debug_assert!(
+                i32::try_from(vertex_offset).is_ok(),
+                "vertex offset {} exceeds the base vertex range",
+                vertex_offset
+            );
+            self.render_draw_list_impl(
+                backend,
+                draw_list,
+                vertex_offset as i32,
+                index_offset,
+                &mut state,
+                draw_data,
+                viewport,
+            )?;
+            if let Some(Callback(callback)) = &self.after_draw_list {
+                callback(index, &self.context);
+            }
+            vertex_offset += draw_list.vtx_buffer().len();
+            index_offset += draw_list.idx_buffer().len();
+        }
+        Ok(state.stats)
+    }
+
+    unsafe fn render_draw_list_impl(
+        &self,
+        backend: &impl RenderBackend,
+        draw_list: &DrawList,
+        base_vertex: i32,
+        index_offset: usize,
+        state: &mut DrawState,
+        draw_data: &DrawData,
+        viewport: &D3D11_VIEWPORT,
+    ) -> Result<()> {
+        let flush = |batch: &mut Option<DrawBatch>, state: &mut DrawState| {
+            let Some(batch) = batch.take() else {
+                return Ok(());
+            };
+            self.draw_batch(backend, batch, base_vertex, state, draw_data, viewport)
+        };
+        // Consecutive commands sharing texture, clip rect and vertex offset
+        // whose indices follow each other are merged into a single draw call.
+        // A batch never spans state changes or draw lists.
+        let mut batch = None;
+        for cmd in draw_list.commands() {
+            match cmd {
+                DrawCmd::Elements {
+                    count,
+                    cmd_params: DrawCmdParams { clip_rect, texture_id, vtx_offset, idx_offset },
+                } => {
+                    let index_offset = index_offset + idx_offset;
+                    match &mut batch {
+                        Some(b)
+                            if b.texture_id == texture_id
+                                && b.clip_rect == clip_rect
+                                && b.vtx_offset == vtx_offset
+                                && b.index_offset + b.count == index_offset =>
+                        {
+                            b.count += count
+                        },
+                        _ => {
+                            flush(&mut batch, state)?;
+                            batch = Some(DrawBatch {
+                                texture_id,
+                                clip_rect,
+                                vtx_offset,
+                                index_offset,
+                                count,
+                            });
+                        },
+                    }
+                },
+                DrawCmd::ResetRenderState => {
+                    flush(&mut batch, state)?;
+                    self.reset_render_state(backend, state, viewport);
+                },
+                DrawCmd::RawCallback { callback, raw_cmd } => {
+                    flush(&mut batch, state)?;
+                    // Depending on the imgui-rs version the reset sentinel may
+                    // not be translated, it must never be called.
+                    if RESET_RENDER_STATE_CALLBACKS.contains(&(callback as usize as isize)) {
+                        self.reset_render_state(backend, state, viewport);
+                        continue;
+                    }
+                    callback(draw_list.raw(), raw_cmd);
+                    // The callback may have issued its own draws on the
+                    // context, restore imgui's state for the commands after it.
+                    self.setup_render_state(backend, viewport);
+                    state.last_tex = TextureId::from(FONT_TEX_ID);
+                },
+            }
+        }
+        flush(&mut batch, state)
+    }
+
+    /// Handles a reset of the render state requested by a draw list, however
+    /// imgui-rs delivers it.
+    unsafe fn reset_render_state(
+        &self,
+        backend: &impl RenderBackend,
+        state: &mut DrawState,
+        viewport: &D3D11_VIEWPORT,
+    ) {
+        // The font texture is bound again along with the rest of the state,
+        // the next command has to select its own. The offsets of the commands
+        // are relative to the draw list, whose position in the shared buffers
+        // is unaffected by binding them again at offset zero.
+        self.setup_render_state(backend, viewport);
+        state.last_tex = TextureId::from(FONT_TEX_ID);
+        if let Some(Callback(callback)) = &self.on_reset_render_state {
+            callback(&self.context);
+        }
+    }
+
+    unsafe fn draw_batch(
+        &self,
+        backend: &impl RenderBackend,
+        batch: DrawBatch,
+        base_vertex: i32,
+        state: &mut DrawState,
+        draw_data: &DrawData,
+        viewport: &D3D11_VIEWPORT,
+    ) -> Result<()> {
+        // The projection stretches the display area over the viewport, which
+        // only matches the framebuffer scale if the viewport was derived from
+        // it. Targets and viewports of other sizes scale the clip rects alike.
+        let clip_scale = [
+            viewport.Width / draw_data.display_size[0],
+            viewport.Height / draw_data.display_size[1],
+        ];
+        // Degenerate or fully clipped batches would draw nothing, skip them
+        // without touching any state.
+        let Some(mut scissor) = Self::scissor_rect(
+            batch.clip_rect,
+            draw_data.display_pos,
+            clip_scale,
+            &self.bound_viewport(viewport),
+            self.flip_y,
+        ) else {
+            return Ok(());
+        };
+        if let Some(bounds) = self.scissor_bounds.filter(|_| self.scissor_test) {
+            scissor.left = scissor.left.max(bounds.left);
+            scissor.top = scissor.top.max(bounds.top);
+            scissor.right = scissor.right.min(bounds.right);
+            scissor.bottom = scissor.bottom.min(bounds.bottom);
+            if scissor.right <= scissor.left || scissor.bottom <= scissor.top {
+                return Ok(());
+            }
+        }
+
+        if batch.texture_id != state.last_tex {
+            let objects = self.objects();
+            let is_font = batch.texture_id.id() == FONT_TEX_ID;
+            let texture = if is_font {
+                &objects.font_resource_view
+            } else {
+                self.textures.get(batch.texture_id).ok_or_else(|| {
+                    log_warn!(
+                        "draw data references invalid texture id {}, registered ids are {:?}",
+                        batch.texture_id.id(),
+                        self.iter_textures().map(|(id, _)| id.id()).collect::<Vec<_>>()
+                    );
+                    RendererError::InvalidTextureId(batch.texture_id)
+                })?
+            };
+            backend.set_texture(texture);
+            if let Some(texture_sampler) = &objects.texture_sampler {
+                backend.set_sampler(if is_font { &objects.font_sampler } else { texture_sampler });
+            }
+            state.last_tex = batch.texture_id;
+            state.stats.texture_binds += 1;
+        }
+
+        if self.scissor_test {
+            backend.set_scissor_rect(scissor);
+        }
+        // imgui starts a command with a vertex offset once a draw list exceeds
+        // the range of 16 bit indices, relative to the start of the list.
+        let base_vertex = base_vertex + batch.vtx_offset as i32;
+        backend.draw_indexed(batch.count as u32, batch.index_offset as u32, base_vertex);
+        state.stats.draw_calls += 1;
+        state.stats.indices_drawn += batch.count as u32;
+        Ok(())
+    }
+
+    /// Projects a clip rect into render target space and intersects it with
+    /// the bound viewport, as drivers differ in how they treat scissors
+    /// extending beyond it, e.g. for windows dragged partially off-screen.
+    ///
+    /// The edges are rounded outwards so that fractional scales never clip
+    /// content the clip rect covers partially.
+    ///
+    /// Returns `None` if the resulting rect is empty.
+    fn scissor_rect(
+        clip_rect: [f32; 4],
+        clip_off: [f32; 2],
+        clip_scale: [f32; 2],
+        viewport: &D3D11_VIEWPORT,
+        flip_y: bool,
+    ) -> Option<RECT> {
+        let mut min_y = (clip_rect[1] - clip_off[1]) * clip_scale[1];
+        let mut max_y = (clip_rect[3] - clip_off[1]) * clip_scale[1];
+        if flip_y {
+            (min_y, max_y) = (viewport.Height - max_y, viewport.Height - min_y);
+        }
+        let clip_min = [
+            ((clip_rect[0] - clip_off[0]) * clip_scale[0]).max(0.0) + viewport.TopLeftX,
+            min_y.max(0.0) + viewport.TopLeftY,
+        ];
+        let clip_max = [
+            ((clip_rect[2] - clip_off[0]) * clip_scale[0]).min(viewport.Width) + viewport.TopLeftX,
+            max_y.min(viewport.Height) + viewport.TopLeftY,
+        ];
+        if clip_max[0] <= clip_min[0] || clip_max[1] <= clip_min[1] {
+            return None;
         }
-        Ok(())
+        // The viewport may start at negative or fractional coordinates, so the
+        // edges can't simply be truncated. `f32::floor` and `f32::ceil` aren't
+        // available without `std`.
+        let floor = |x: f32| if (x as i32 as f32) > x { x as i32 - 1 } else { x as i32 };
+        let ceil = |x: f32| if (x as i32 as f32) < x { x as i32 + 1 } else { x as i32 };
+        Some(RECT {
+            left: floor(clip_min[0]),
+            top: floor(clip_min[1]),
+            right: ceil(clip_max[0]),
+            bottom: ceil(clip_max[1]),
+        })
     }
 
-    unsafe fn render_impl(&self, draw_data: &DrawData) -> Result<()> {
-        let clip_off = draw_data.display_pos;
-        let clip_scale = draw_data.framebuffer_scale;
-        let mut vertex_offset = 0;
-        let mut index_offset = 0;
-        let mut last_tex = TextureId::from(FONT_TEX_ID);
-        let context = &self.context;
-        context.PSSetShaderResources(0, Some(&[self.font_resource_view.clone()]));
-        for draw_list in draw_data.draw_lists() {
-            for cmd in draw_list.commands() {
-                match cmd {
-                    DrawCmd::Elements {
-                        count,
-                        cmd_params: DrawCmdParams { clip_rect, texture_id, .. },
-                    } => {
-                        if texture_id != last_tex {
-                            let texture = if texture_id.id() == FONT_TEX_ID {
-                                self.font_resource_view.clone()
-                            } else {
-                                self.textures
-                                    .get(texture_id)
-                                    .ok_or(DXGI_ERROR_INVALID_CALL)?
-                                    .clone()
-                            };
-                            context.PSSetShaderResources(0, Some(&[texture]));
-                            last_tex = texture_id;
-                        }
-
-                        let r = RECT {
-                            left: ((clip_rect[0] - clip_off[0]) * clip_scale[0]) as i32,
-                            top: ((clip_rect[1] - clip_off[1]) * clip_scale[1]) as i32,
-                            right: ((clip_rect[2] - clip_off[0]) * clip_scale[0]) as i32,
-                            bottom: ((clip_rect[3] - clip_off[1]) * clip_scale[1]) as i32,
-                        };
-                        context.RSSetScissorRects(Some(&[r]));
-                        context.DrawIndexed(
-                            count as u32,
-                            index_offset as u32,
-                            vertex_offset as i32,
-                        );
-                        index_offset += count;
-                    },
-                    DrawCmd::ResetRenderState => self.setup_render_state(draw_data),
-                    DrawCmd::RawCallback { callback, raw_cmd } => {
-                        callback(draw_list.raw(), raw_cmd)
-                    },
-                }
-            }
-            vertex_offset += draw_list.vtx_buffer().len();
+    /// The viewport as bound to the device context, moved by the offset set
+    /// via [`Renderer::set_viewport_offset`].
+    fn bound_viewport(&self, viewport: &D3D11_VIEWPORT) -> D3D11_VIEWPORT {
+        D3D11_VIEWPORT {
+            TopLeftX: viewport.TopLeftX + self.viewport_offset[0],
+            TopLeftY: viewport.TopLeftY + self.viewport_offset[1],
+            ..*viewport
         }
-        Ok(())
     }
 
-    unsafe fn setup_render_state(&self, draw_data: &DrawData) {
-        let ctx = &self.context;
-        let vp = D3D11_VIEWPORT {
-            TopLeftX: 0.0,
-            TopLeftY: 0.0,
-            Width: draw_data.display_size[0] * draw_data.framebuffer_scale[0],
-            Height: draw_data.display_size[1] * draw_data.framebuffer_scale[1],
-            MinDepth: 0.0,
-            MaxDepth: 1.0,
+    unsafe fn setup_render_state(&self, backend: &impl RenderBackend, viewport: &D3D11_VIEWPORT) {
+        let objects = self.objects();
+        let (vertex_shader, input_layout) = match &self.custom_vertex_shader {
+            Some((vertex_shader, input_layout)) => (vertex_shader, input_layout),
+            None => (&objects.vertex_shader, &objects.input_layout),
         };
-        let draw_fmt = if mem::size_of::<DrawIdx>() == 2 {
-            DXGI_FORMAT_R16_UINT
-        } else {
-            DXGI_FORMAT_R32_UINT
+        let pixel_shader = match (&self.custom_pixel_shader, self.target_format) {
+            (Some(pixel_shader), _) => pixel_shader,
+            (None, Some(format)) => &objects.format_pixel_shaders[format as usize],
+            (None, None) => &objects.pixel_shader,
         };
-        let stride = mem::size_of::<DrawVert>() as u32;
-        let blend_factor = 0.0;
+        let rasterizer_state = match &self.custom_rasterizer_state {
+            Some(rasterizer_state) => rasterizer_state,
+            None if self.wireframe => &objects.wireframe_rasterizer_state,
+            None => &objects.rasterizer_state,
+        };
+        backend.setup_render_state(&RenderState {
+            viewport: self.bound_viewport(viewport),
+            input_layout,
+            vertex_buffer: objects.vertex_buffer.as_option(),
+            vertex_stride: self.vertex_stride as u32,
+            index_buffer: objects.index_buffer.get_buf(),
+            vertex_shader,
+            vertex_constant_buffer: &objects.constant_buffer,
+            pixel_shader,
+            pixel_constant_buffer: &objects.pixel_constant_buffer,
+            texture: &objects.font_resource_view,
+            sampler: &objects.font_sampler,
+            unbind_shader_stages: !self.config.keep_shader_stages,
+            blend_state: self.custom_blend_state.as_ref().unwrap_or(&objects.blend_state),
+            depth_stencil_state: &objects.depth_stencil_state,
+            rasterizer_state,
+        });
+    }
 
-        ctx.RSSetViewports(Some(&[vp]));
-        ctx.IASetInputLayout(&self.input_layout);
-        ctx.IASetVertexBuffers(
-            0,
-            1,
-            Some(&Some(self.vertex_buffer.get_buf().clone())),
-            Some(&stride),
-            Some(&0),
-        );
-        ctx.IASetIndexBuffer(self.index_buffer.get_buf(), draw_fmt, 0);
-        ctx.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
-        ctx.VSSetShader(&self.vertex_shader, None);
-        ctx.VSSetConstantBuffers(0, Some(&[self.constant_buffer.clone()]));
-        ctx.PSSetShader(&self.pixel_shader, None);
-        ctx.PSSetSamplers(0, Some(&[self.font_sampler.clone()]));
-        ctx.GSSetShader(None, None);
-        ctx.HSSetShader(None, None);
-        ctx.DSSetShader(None, None);
-        ctx.CSSetShader(None, None);
-        ctx.OMSetBlendState(&self.blend_state, Some(&blend_factor), 0xFFFFFFFF);
-        ctx.OMSetDepthStencilState(&self.depth_stencil_state, 0);
-        ctx.RSSetState(&self.rasterizer_state);
-    }
-
-    unsafe fn create_vertex_buffer(device: &ID3D11Device, vtx_count: usize) -> Result<Buffer> {
-        let len = vtx_count + VERTEX_BUF_ADD_CAPACITY;
+    unsafe fn create_vertex_buffer(
+        device: &ID3D11Device,
+        vtx_count: usize,
+        headroom: usize,
+        stride: usize,
+    ) -> Result<Buffer> {
+        let len = buffer_len(vtx_count, headroom)?;
         let desc = D3D11_BUFFER_DESC {
-            ByteWidth: (len * mem::size_of::<DrawVert>()) as u32,
+            ByteWidth: buffer_byte_width(len, stride)?,
             Usage: D3D11_USAGE_DYNAMIC,
             BindFlags: D3D11_BIND_VERTEX_BUFFER,
             CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
@@ -245,13 +2505,19 @@ impl Renderer {
 
         let mut uninitialized_buffer = None;
         device.CreateBuffer(&desc, None, Some(&mut uninitialized_buffer))?;
-        Ok(Buffer(uninitialized_buffer.unwrap(), len))
+        let buffer = uninitialized_buffer.unwrap();
+        set_debug_name(&buffer, "imgui-dx11 vertex buffer");
+        Ok(Buffer(Some(buffer), len))
     }
 
-    unsafe fn create_index_buffer(device: &ID3D11Device, idx_count: usize) -> Result<Buffer> {
-        let len = idx_count + INDEX_BUF_ADD_CAPACITY;
+    unsafe fn create_index_buffer(
+        device: &ID3D11Device,
+        idx_count: usize,
+        headroom: usize,
+    ) -> Result<Buffer> {
+        let len = buffer_len(idx_count, headroom)?;
         let desc = D3D11_BUFFER_DESC {
-            ByteWidth: (len * mem::size_of::<DrawIdx>()) as u32,
+            ByteWidth: buffer_byte_width(len, mem::size_of::<DrawIdx>())?,
             Usage: D3D11_USAGE_DYNAMIC,
             BindFlags: D3D11_BIND_INDEX_BUFFER,
             CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
@@ -261,13 +2527,44 @@ impl Renderer {
 
         let mut uninitialized_buffer = None;
         device.CreateBuffer(&desc, None, Some(&mut uninitialized_buffer))?;
-        Ok(Buffer(uninitialized_buffer.unwrap(), len))
+        let buffer = uninitialized_buffer.unwrap();
+        set_debug_name(&buffer, "imgui-dx11 index buffer");
+        Ok(Buffer(Some(buffer), len))
+    }
+
+    unsafe fn write_pixel_constants(&mut self) {
+        if self.pixel_constants_dirty {
+            self.context.UpdateSubresource(
+                &self.objects().pixel_constant_buffer,
+                0,
+                None,
+                (&self.pixel_constants as *const PixelConstantBuffer).cast(),
+                0,
+                0,
+            );
+            self.pixel_constants_dirty = false;
+        }
+    }
+
+    unsafe fn write_buffers(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.write_geometry(
+            draw_data.draw_lists(),
+            draw_data.total_vtx_count as usize,
+            draw_data.total_idx_count as usize,
+        )?;
+        self.write_projection(draw_data)
     }
 
-    unsafe fn write_buffers(&self, draw_data: &DrawData) -> Result<()> {
+    unsafe fn write_geometry<'a>(
+        &self,
+        draw_lists: impl Iterator<Item = &'a DrawList>,
+        vtx_count: usize,
+        idx_count: usize,
+    ) -> Result<()> {
+        let objects = self.objects();
         let mut vtx_resource = D3D11_MAPPED_SUBRESOURCE::default();
         self.context.Map(
-            self.vertex_buffer.get_buf(),
+            objects.vertex_buffer.get_buf(),
             0,
             D3D11_MAP_WRITE_DISCARD,
             0,
@@ -275,65 +2572,159 @@ impl Renderer {
         )?;
         let mut idx_resource = D3D11_MAPPED_SUBRESOURCE::default();
         self.context.Map(
-            self.index_buffer.get_buf(),
+            objects.index_buffer.get_buf(),
             0,
             D3D11_MAP_WRITE_DISCARD,
             0,
             Some(&mut idx_resource),
         )?;
 
+        // Vertices are copied as bytes, as they may be wider than `DrawVert`.
         let mut vtx_dst = slice::from_raw_parts_mut(
-            vtx_resource.pData.cast::<DrawVert>(),
-            draw_data.total_vtx_count as usize,
-        );
-        let mut idx_dst = slice::from_raw_parts_mut(
-            idx_resource.pData.cast::<DrawIdx>(),
-            draw_data.total_idx_count as usize,
+            vtx_resource.pData.cast::<u8>(),
+            vtx_count * self.vertex_stride,
         );
+        let mut idx_dst =
+            slice::from_raw_parts_mut(idx_resource.pData.cast::<DrawIdx>(), idx_count);
 
         for (vbuf, ibuf) in
-            draw_data.draw_lists().map(|draw_list| (draw_list.vtx_buffer(), draw_list.idx_buffer()))
+            draw_lists.map(|draw_list| (draw_list.vtx_buffer(), draw_list.idx_buffer()))
         {
+            let vbuf =
+                slice::from_raw_parts(vbuf.as_ptr().cast::<u8>(), vbuf.len() * self.vertex_stride);
             vtx_dst[..vbuf.len()].copy_from_slice(vbuf);
             idx_dst[..ibuf.len()].copy_from_slice(ibuf);
             vtx_dst = &mut vtx_dst[vbuf.len()..];
             idx_dst = &mut idx_dst[ibuf.len()..];
         }
 
-        self.context.Unmap(self.vertex_buffer.get_buf(), 0);
-        self.context.Unmap(self.index_buffer.get_buf(), 0);
+        self.context.Unmap(objects.vertex_buffer.get_buf(), 0);
+        self.context.Unmap(objects.index_buffer.get_buf(), 0);
+        Ok(())
+    }
+
+    unsafe fn write_projection(&mut self, draw_data: &DrawData) -> Result<()> {
+        let mut mvp = Self::projection_matrix(draw_data);
+        if self.flip_y {
+            // Swapping top and bottom negates the Y scale and offset.
+            mvp[1][1] = -mvp[1][1];
+            mvp[3][1] = -mvp[3][1];
+        }
+        mvp[3][2] = self.depth;
+        let mvp = mul_matrix(&self.transform, &mvp);
+        // The buffer keeps its contents between frames on the immediate
+        // context, while deferred contexts require discarding it every time.
+        // A buffer of the application may have been written to in between.
+        if self.last_mvp == Some(mvp)
+            && self.context.GetType() == D3D11_DEVICE_CONTEXT_IMMEDIATE
+            && self.config.vertex_constant_buffer.is_none()
+        {
+            return Ok(());
+        }
 
+        let objects = self.objects();
         let mut mapped_resource = D3D11_MAPPED_SUBRESOURCE::default();
         self.context.Map(
-            &self.constant_buffer,
+            &objects.constant_buffer,
             0,
             D3D11_MAP_WRITE_DISCARD,
             0,
             Some(&mut mapped_resource),
         )?;
-        let l = draw_data.display_pos[0];
-        let r = draw_data.display_pos[0] + draw_data.display_size[0];
-        let t = draw_data.display_pos[1];
-        let b = draw_data.display_pos[1] + draw_data.display_size[1];
-        let mvp = [
-            [2.0 / (r - l), 0.0, 0.0, 0.0],
-            [0.0, 2.0 / (t - b), 0.0, 0.0],
-            [0.0, 0.0, 0.5, 0.0],
-            [(r + l) / (l - r), (t + b) / (b - t), 0.5, 1.0],
-        ];
         *mapped_resource.pData.cast::<VertexConstantBuffer>() = VertexConstantBuffer { mvp };
-        self.context.Unmap(&self.constant_buffer, 0);
+        self.context.Unmap(&objects.constant_buffer, 0);
+        self.last_mvp = Some(mvp);
+
+        Ok(())
+    }
 
+    /// Rejects dimensions `CreateTexture2D` would fail on with an opaque
+    /// `E_INVALIDARG`.
+    unsafe fn check_texture_size(device: &ID3D11Device, width: u32, height: u32) -> Result<()> {
+        let max = if device.GetFeatureLevel().0 >= D3D_FEATURE_LEVEL_11_0.0 {
+            D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION
+        } else {
+            // D3D10_REQ_TEXTURE2D_U_OR_V_DIMENSION
+            8192
+        };
+        if width > max || height > max {
+            return Err(RendererError::TextureTooLarge { width, height, max });
+        }
+        Ok(())
+    }
+
+    /// Rejects objects supplied by the application that belong to another
+    /// device, e.g. one that was lost since.
+    unsafe fn check_device<T: Interface>(object: &T, device: &ID3D11Device) -> Result<()> {
+        let mut owner = None;
+        object.cast::<ID3D11DeviceChild>()?.GetDevice(&mut owner);
+        if owner.as_ref() != Some(device) {
+            return Err(RendererError::DeviceMismatch);
+        }
         Ok(())
     }
 
+    unsafe fn create_texture_view(
+        device: &ID3D11Device,
+        width: u32,
+        height: u32,
+        format: DXGI_FORMAT,
+        data: &[u8],
+    ) -> Result<ID3D11ShaderResourceView> {
+        let expected = width as usize * height as usize * 4;
+        if data.len() != expected {
+            return Err(RendererError::TextureDataSize { expected, actual: data.len() });
+        }
+        Self::check_texture_size(device, width, height)?;
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: width,
+            Height: height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: format,
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE,
+            ..Default::default()
+        };
+        let sub_resource = D3D11_SUBRESOURCE_DATA {
+            pSysMem: data.as_ptr().cast(),
+            SysMemPitch: width * 4,
+            SysMemSlicePitch: 0,
+        };
+        let mut uninit_texture = None;
+        device.CreateTexture2D(&desc, Some(&sub_resource), Some(&mut uninit_texture))?;
+        let texture = uninit_texture.unwrap();
+
+        let mut srv_desc = D3D11_SHADER_RESOURCE_VIEW_DESC {
+            Format: format,
+            ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
+            ..Default::default()
+        };
+        srv_desc.Anonymous.Texture2D.MipLevels = desc.MipLevels;
+        srv_desc.Anonymous.Texture2D.MostDetailedMip = 0;
+        let mut uninit_texture_view = None;
+        device.CreateShaderResourceView(
+            &texture,
+            Some(&srv_desc),
+            Some(&mut uninit_texture_view),
+        )?;
+        Ok(uninit_texture_view.unwrap())
+    }
+
     unsafe fn create_font_texture(
         mut fonts: &mut imgui::FontAtlas,
         device: &ID3D11Device,
-    ) -> Result<(ID3D11ShaderResourceView, ID3D11SamplerState)> {
+        context: &ID3D11DeviceContext,
+        config: &RendererBuilder,
+    ) -> Result<(ID3D11ShaderResourceView, ID3D11SamplerState, [u32; 2])> {
         let fa_tex = fonts.build_rgba32_texture();
+        let size = [fa_tex.width, fa_tex.height];
+        log_debug!("building {}x{} font texture", fa_tex.width, fa_tex.height);
+        Self::check_texture_size(device, fa_tex.width, fa_tex.height)?;
 
-        let desc = D3D11_TEXTURE2D_DESC {
+        let mut desc = D3D11_TEXTURE2D_DESC {
             Width: fa_tex.width,
             Height: fa_tex.height,
             MipLevels: 1,
@@ -351,15 +2742,28 @@ impl Renderer {
         };
 
         let mut uninit_texture = None;
-        device.CreateTexture2D(&desc, Some(&sub_resource), Some(&mut uninit_texture))?;
+        if config.font_mipmaps {
+            // GenerateMips requires the texture to be bindable as a render target,
+            // and the initial data can only describe the top level, so upload it
+            // separately.
+            desc.MipLevels = 0;
+            desc.BindFlags = D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET;
+            desc.MiscFlags = D3D11_RESOURCE_MISC_GENERATE_MIPS;
+            device.CreateTexture2D(&desc, None, Some(&mut uninit_texture))?;
+        } else {
+            device.CreateTexture2D(&desc, Some(&sub_resource), Some(&mut uninit_texture))?;
+        }
         let texture = uninit_texture.unwrap();
+        set_debug_name(&texture, "imgui-dx11 font texture");
 
         let mut srv_desc = D3D11_SHADER_RESOURCE_VIEW_DESC {
             Format: DXGI_FORMAT_R8G8B8A8_UNORM,
             ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
             ..Default::default()
         };
-        srv_desc.Anonymous.Texture2D.MipLevels = desc.MipLevels;
+        // u32::MAX selects all mip levels down to the least detailed one.
+        srv_desc.Anonymous.Texture2D.MipLevels =
+            if config.font_mipmaps { u32::MAX } else { desc.MipLevels };
         srv_desc.Anonymous.Texture2D.MostDetailedMip = 0;
         let mut uninit_font_texture_view = None;
         device.CreateShaderResourceView(
@@ -368,33 +2772,75 @@ impl Renderer {
             Some(&mut uninit_font_texture_view),
         )?;
         let font_texture_view = uninit_font_texture_view.unwrap();
+        set_debug_name(&font_texture_view, "imgui-dx11 font texture view");
+
+        if config.font_mipmaps {
+            context.UpdateSubresource(
+                &texture,
+                0,
+                None,
+                sub_resource.pSysMem,
+                sub_resource.SysMemPitch,
+                0,
+            );
+            context.GenerateMips(&font_texture_view);
+        }
 
         fonts.tex_id = TextureId::from(FONT_TEX_ID);
 
+        if let Some(font_sampler) = &config.font_sampler {
+            Self::check_device(font_sampler, device)?;
+            return Ok((font_texture_view, font_sampler.clone(), size));
+        }
         let desc = D3D11_SAMPLER_DESC {
-            Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+            Filter: config.font_filter.to_d3d11(),
             AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
             AddressV: D3D11_TEXTURE_ADDRESS_WRAP,
             AddressW: D3D11_TEXTURE_ADDRESS_WRAP,
             MipLODBias: 0.0,
             ComparisonFunc: D3D11_COMPARISON_ALWAYS,
             MinLOD: 0.0,
-            MaxLOD: 0.0,
+            MaxLOD: if config.font_mipmaps { D3D11_FLOAT32_MAX } else { 0.0 },
             ..Default::default()
         };
         let mut uninit_font_sampler = None;
         device.CreateSamplerState(&desc, Some(&mut uninit_font_sampler))?;
-        Ok((font_texture_view, uninit_font_sampler.unwrap()))
+        let font_sampler = uninit_font_sampler.unwrap();
+        set_debug_name(&font_sampler, "imgui-dx11 font sampler");
+        Ok((font_texture_view, font_sampler, size))
+    }
+
+    unsafe fn create_texture_sampler(
+        device: &ID3D11Device,
+        max_anisotropy: u32,
+    ) -> Result<ID3D11SamplerState> {
+        let desc = D3D11_SAMPLER_DESC {
+            Filter: D3D11_FILTER_ANISOTROPIC,
+            AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
+            AddressV: D3D11_TEXTURE_ADDRESS_WRAP,
+            AddressW: D3D11_TEXTURE_ADDRESS_WRAP,
+            MipLODBias: 0.0,
+            MaxAnisotropy: max_anisotropy,
+            ComparisonFunc: D3D11_COMPARISON_ALWAYS,
+            MinLOD: 0.0,
+            MaxLOD: D3D11_FLOAT32_MAX,
+            ..Default::default()
+        };
+        let mut uninit_texture_sampler = None;
+        device.CreateSamplerState(&desc, Some(&mut uninit_texture_sampler))?;
+        let texture_sampler = uninit_texture_sampler.unwrap();
+        set_debug_name(&texture_sampler, "imgui-dx11 texture sampler");
+        Ok(texture_sampler)
     }
 
     unsafe fn create_vertex_shader(
         device: &ID3D11Device,
-    ) -> Result<(ID3D11VertexShader, ID3D11InputLayout, ID3D11Buffer)> {
-        const VERTEX_SHADER: &[u8] =
-            include_bytes!(concat!(env!("OUT_DIR"), "/vertex_shader.vs_4_0"));
+        bytecode: &[u8],
+    ) -> Result<(ID3D11VertexShader, ID3D11InputLayout)> {
         let mut uninit_vs_shader = None;
-        device.CreateVertexShader(VERTEX_SHADER, None, Some(&mut uninit_vs_shader))?;
+        device.CreateVertexShader(bytecode, None, Some(&mut uninit_vs_shader))?;
         let vs_shader = uninit_vs_shader.unwrap();
+        set_debug_name(&vs_shader, "imgui-dx11 vertex shader");
 
         let local_layout = [
             D3D11_INPUT_ELEMENT_DESC {
@@ -427,9 +2873,29 @@ impl Renderer {
         ];
 
         let mut uninit_input_layout = None;
-        device.CreateInputLayout(&local_layout, VERTEX_SHADER, Some(&mut uninit_input_layout))?;
+        device.CreateInputLayout(&local_layout, bytecode, Some(&mut uninit_input_layout))?;
         let input_layout = uninit_input_layout.unwrap();
+        set_debug_name(&input_layout, "imgui-dx11 input layout");
+        Ok((vs_shader, input_layout))
+    }
 
+    unsafe fn create_vertex_constant_buffer(
+        device: &ID3D11Device,
+        config: &RendererBuilder,
+    ) -> Result<ID3D11Buffer> {
+        if let Some(constant_buffer) = &config.vertex_constant_buffer {
+            Self::check_device(constant_buffer, device)?;
+            let mut desc = D3D11_BUFFER_DESC::default();
+            constant_buffer.GetDesc(&mut desc);
+            if (desc.ByteWidth as usize) < mem::size_of::<VertexConstantBuffer>()
+                || desc.Usage != D3D11_USAGE_DYNAMIC
+                || (desc.BindFlags & D3D11_BIND_CONSTANT_BUFFER).0 == 0
+                || (desc.CPUAccessFlags & D3D11_CPU_ACCESS_WRITE).0 == 0
+            {
+                return Err(RendererError::InvalidConstantBuffer);
+            }
+            return Ok(constant_buffer.clone());
+        }
         let desc = D3D11_BUFFER_DESC {
             ByteWidth: mem::size_of::<VertexConstantBuffer>() as _,
             Usage: D3D11_USAGE_DYNAMIC,
@@ -438,78 +2904,560 @@ impl Renderer {
             MiscFlags: D3D11_RESOURCE_MISC_FLAG::default(),
             StructureByteStride: 0,
         };
-        let mut uninit_vertex_constant_buffer = None;
-        device.CreateBuffer(&desc, None, Some(&mut uninit_vertex_constant_buffer))?;
-        Ok((vs_shader, input_layout, uninit_vertex_constant_buffer.unwrap()))
+        let mut uninit_vertex_constant_buffer = None;
+        device.CreateBuffer(&desc, None, Some(&mut uninit_vertex_constant_buffer))?;
+        let constant_buffer = uninit_vertex_constant_buffer.unwrap();
+        set_debug_name(&constant_buffer, "imgui-dx11 vertex constant buffer");
+        Ok(constant_buffer)
+    }
+
+    unsafe fn create_pixel_shader(
+        device: &ID3D11Device,
+        bytecode: &[u8],
+    ) -> Result<ID3D11PixelShader> {
+        let mut uninit_pixel_shader = None;
+        device.CreatePixelShader(bytecode, None, Some(&mut uninit_pixel_shader))?;
+        let pixel_shader = uninit_pixel_shader.unwrap();
+        set_debug_name(&pixel_shader, "imgui-dx11 pixel shader");
+        Ok(pixel_shader)
+    }
+
+    unsafe fn create_pixel_constant_buffer(
+        device: &ID3D11Device,
+        constants: &PixelConstantBuffer,
+    ) -> Result<ID3D11Buffer> {
+        let desc = D3D11_BUFFER_DESC {
+            ByteWidth: mem::size_of::<PixelConstantBuffer>() as _,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+            CPUAccessFlags: D3D11_CPU_ACCESS_FLAG::default(),
+            MiscFlags: D3D11_RESOURCE_MISC_FLAG::default(),
+            StructureByteStride: 0,
+        };
+        let sub_resource = D3D11_SUBRESOURCE_DATA {
+            pSysMem: (constants as *const PixelConstantBuffer).cast(),
+            SysMemPitch: 0,
+            SysMemSlicePitch: 0,
+        };
+        let mut uninit_constant_buffer = None;
+        device.CreateBuffer(&desc, Some(&sub_resource), Some(&mut uninit_constant_buffer))?;
+        let constant_buffer = uninit_constant_buffer.unwrap();
+        set_debug_name(&constant_buffer, "imgui-dx11 pixel constant buffer");
+        Ok(constant_buffer)
+    }
+
+    unsafe fn create_states(
+        device: &ID3D11Device,
+        config: &RendererBuilder,
+    ) -> Result<(
+        ID3D11BlendState,
+        ID3D11RasterizerState,
+        ID3D11RasterizerState,
+        ID3D11DepthStencilState,
+    )> {
+        let src_blend = match config.blend_mode {
+            BlendMode::Straight => D3D11_BLEND_SRC_ALPHA,
+            BlendMode::Premultiplied => D3D11_BLEND_ONE,
+        };
+        let (src_blend_alpha, dest_blend_alpha, blend_op_alpha) = config.alpha_blend.unwrap_or((
+            D3D11_BLEND_ONE,
+            D3D11_BLEND_INV_SRC_ALPHA,
+            D3D11_BLEND_OP_ADD,
+        ));
+        // Feature level 10.0 doesn't support independent blending, which isn't
+        // needed anyway. Without it Direct3D only reads the first render
+        // target's desc and applies it to all bound render targets.
+        let mut desc = D3D11_BLEND_DESC {
+            AlphaToCoverageEnable: false.into(),
+            IndependentBlendEnable: false.into(),
+            ..Default::default()
+        };
+        desc.RenderTarget[0] = D3D11_RENDER_TARGET_BLEND_DESC {
+            BlendEnable: true.into(),
+            SrcBlend: src_blend,
+            DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+            BlendOp: D3D11_BLEND_OP_ADD,
+            SrcBlendAlpha: src_blend_alpha,
+            DestBlendAlpha: dest_blend_alpha,
+            BlendOpAlpha: blend_op_alpha,
+            RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+        };
+
+        let mut uninit_blend_state = None;
+        device.CreateBlendState(&desc, Some(&mut uninit_blend_state))?;
+
+        let desc = D3D11_RASTERIZER_DESC {
+            FillMode: D3D11_FILL_SOLID,
+            CullMode: D3D11_CULL_NONE,
+            DepthClipEnable: true.into(),
+            ScissorEnable: (!config.disable_scissor).into(),
+            AntialiasedLineEnable: config.antialiased_lines.into(),
+            ..Default::default()
+        };
+        let mut uninit_rasterizer_state = None;
+        device.CreateRasterizerState(&desc, Some(&mut uninit_rasterizer_state))?;
+        let desc = D3D11_RASTERIZER_DESC { FillMode: D3D11_FILL_WIREFRAME, ..desc };
+        let mut uninit_wireframe_rasterizer_state = None;
+        device.CreateRasterizerState(&desc, Some(&mut uninit_wireframe_rasterizer_state))?;
+
+        let stencil_op_desc = D3D11_DEPTH_STENCILOP_DESC {
+            StencilFailOp: D3D11_STENCIL_OP_KEEP,
+            StencilDepthFailOp: D3D11_STENCIL_OP_KEEP,
+            StencilPassOp: D3D11_STENCIL_OP_KEEP,
+            StencilFunc: D3D11_COMPARISON_ALWAYS,
+        };
+        let desc = D3D11_DEPTH_STENCIL_DESC {
+            DepthEnable: config.depth_func.is_some().into(),
+            DepthWriteMask: if config.depth_func.is_some() {
+                D3D11_DEPTH_WRITE_MASK_ZERO
+            } else {
+                D3D11_DEPTH_WRITE_MASK_ALL
+            },
+            DepthFunc: config.depth_func.unwrap_or(D3D11_COMPARISON_ALWAYS),
+            StencilEnable: false.into(),
+            StencilReadMask: 0,
+            StencilWriteMask: 0,
+            FrontFace: stencil_op_desc,
+            BackFace: stencil_op_desc,
+        };
+        let mut uninit_depth_stencil_state = None;
+        device.CreateDepthStencilState(&desc, Some(&mut uninit_depth_stencil_state))?;
+        let blend_state = uninit_blend_state.unwrap();
+        let rasterizer_state = uninit_rasterizer_state.unwrap();
+        let wireframe_rasterizer_state = uninit_wireframe_rasterizer_state.unwrap();
+        let depth_stencil_state = uninit_depth_stencil_state.unwrap();
+        set_debug_name(&blend_state, "imgui-dx11 blend state");
+        set_debug_name(&rasterizer_state, "imgui-dx11 rasterizer state");
+        set_debug_name(&wireframe_rasterizer_state, "imgui-dx11 wireframe rasterizer state");
+        set_debug_name(&depth_stencil_state, "imgui-dx11 depth stencil state");
+        Ok((blend_state, rasterizer_state, wireframe_rasterizer_state, depth_stencil_state))
+    }
+}
+
+/// Multiplies two column-major 4x4 matrices, `a * b`.
+fn mul_matrix(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut result = [[0.0; 4]; 4];
+    for (col, result_col) in result.iter_mut().enumerate() {
+        for (row, value) in result_col.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+    result
+}
+
+/// A [`Renderer`] that can be sent to another thread.
+///
+/// Derefs to the wrapped renderer.
+#[derive(Debug)]
+pub struct SendRenderer(Renderer);
+
+// SAFETY: upheld by the caller of `SendRenderer::new`.
+unsafe impl Send for SendRenderer {}
+
+impl SendRenderer {
+    /// Wraps the renderer so it can be moved across threads.
+    ///
+    /// # Safety
+    ///
+    /// The device context of the renderer must only ever be used from one
+    /// thread at a time, including by the application outside the renderer.
+    /// When rendering into the immediate context, all other uses of it have to
+    /// happen on the thread the renderer is moved to. The same applies to the
+    /// [`TextureHandle`]s of the renderer.
+    #[inline]
+    pub unsafe fn new(renderer: Renderer) -> Self {
+        SendRenderer(renderer)
+    }
+
+    /// Unwraps the renderer.
+    #[inline]
+    pub fn into_inner(self) -> Renderer {
+        self.0
+    }
+}
+
+impl core::ops::Deref for SendRenderer {
+    type Target = Renderer;
+
+    #[inline]
+    fn deref(&self) -> &Renderer {
+        &self.0
+    }
+}
+
+impl core::ops::DerefMut for SendRenderer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Renderer {
+        &mut self.0
+    }
+}
+
+/// A scope sharing a single backup of the device context state between render
+/// calls, created by [`Renderer::begin`].
+#[derive(Debug)]
+pub struct RenderSession<'a> {
+    renderer: &'a mut Renderer,
+}
+
+impl RenderSession<'_> {
+    /// Renders the given [`DrawData`] like [`Renderer::render`], leaving the
+    /// state to be restored when the session ends.
+    pub fn render(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.renderer.render_no_backup(draw_data)
+    }
+}
+
+impl Drop for RenderSession<'_> {
+    fn drop(&mut self) {
+        self.renderer.state_backup.restore();
+    }
+}
+
+/// An owning handle to a texture registered with a [`Renderer`], returned by
+/// the `register_*_owned` functions like
+/// [`Renderer::register_rgba8_texture_owned`].
+///
+/// Dropping the handle removes the texture from the registry at the start of
+/// the next render call. Dropping it after the renderer does nothing.
+#[derive(Debug)]
+pub struct TextureHandle {
+    id: TextureId,
+    generation: u32,
+    dropped_textures: Weak<RefCell<Vec<(TextureId, u32)>>>,
+}
+
+impl TextureHandle {
+    /// The id of the texture, to be passed to imgui.
+    #[inline]
+    pub fn id(&self) -> TextureId {
+        self.id
+    }
+}
+
+impl Drop for TextureHandle {
+    fn drop(&mut self) {
+        if let Some(dropped_textures) = self.dropped_textures.upgrade() {
+            dropped_textures.borrow_mut().push((self.id, self.generation));
+        }
+    }
+}
+
+/// Creates a device on the WARP software rasterizer, which needs no GPU and
+/// allows creating a [`Renderer`] headlessly, e.g. in tests running on CI.
+///
+/// ```ignore
+/// let device = imgui_dx11_renderer::create_warp_device()?;
+/// let renderer = Renderer::new(&mut imgui, &device)?;
+/// ```
+#[cfg(any(test, feature = "test-support"))]
+pub fn create_warp_device() -> Result<ID3D11Device> {
+    let feature_levels = [D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_10_1, D3D_FEATURE_LEVEL_10_0];
+    let mut device = None;
+    unsafe {
+        D3D11CreateDevice(
+            None,
+            D3D_DRIVER_TYPE_WARP,
+            None,
+            D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+            Some(&feature_levels),
+            D3D11_SDK_VERSION,
+            Some(&mut device),
+            None,
+            None,
+        )?;
+    }
+    Ok(device.unwrap())
+}
+
+fn buffer_len(count: usize, headroom: usize) -> Result<usize> {
+    count.checked_add(headroom).ok_or(RendererError::BufferTooLarge { len: count })
+}
+
+/// The byte width of a buffer holding `len` elements of `elem_size` bytes,
+/// failing instead of silently truncating sizes that don't fit into a `u32`.
+fn buffer_byte_width(len: usize, elem_size: usize) -> Result<u32> {
+    len.checked_mul(elem_size)
+        .and_then(|size| u32::try_from(size).ok())
+        .ok_or(RendererError::BufferTooLarge { len })
+}
+
+fn is_typeless_format(format: DXGI_FORMAT) -> bool {
+    matches!(
+        format,
+        DXGI_FORMAT_R32G32B32A32_TYPELESS
+            | DXGI_FORMAT_R32G32B32_TYPELESS
+            | DXGI_FORMAT_R16G16B16A16_TYPELESS
+            | DXGI_FORMAT_R32G32_TYPELESS
+            | DXGI_FORMAT_R32G8X24_TYPELESS
+            | DXGI_FORMAT_R10G10B10A2_TYPELESS
+            | DXGI_FORMAT_R8G8B8A8_TYPELESS
+            | DXGI_FORMAT_R16G16_TYPELESS
+            | DXGI_FORMAT_R32_TYPELESS
+            | DXGI_FORMAT_R24G8_TYPELESS
+            | DXGI_FORMAT_R8G8_TYPELESS
+            | DXGI_FORMAT_R16_TYPELESS
+            | DXGI_FORMAT_R8_TYPELESS
+            | DXGI_FORMAT_BC1_TYPELESS
+            | DXGI_FORMAT_BC2_TYPELESS
+            | DXGI_FORMAT_BC3_TYPELESS
+            | DXGI_FORMAT_BC4_TYPELESS
+            | DXGI_FORMAT_BC5_TYPELESS
+            | DXGI_FORMAT_B8G8R8A8_TYPELESS
+            | DXGI_FORMAT_B8G8R8X8_TYPELESS
+            | DXGI_FORMAT_BC6H_TYPELESS
+            | DXGI_FORMAT_BC7_TYPELESS
+    )
+}
+
+/// Attaches a name to a D3D11 object that shows up in graphics debuggers such
+/// as RenderDoc or PIX.
+#[cfg(feature = "debug")]
+unsafe fn set_debug_name<T: Interface>(object: &T, name: &str) {
+    if let Ok(child) = object.cast::<ID3D11DeviceChild>() {
+        // Naming is best effort, a failure here must not affect rendering.
+        let _ = child.SetPrivateData(
+            &WKPDID_D3DDebugObjectName,
+            name.len() as u32,
+            Some(name.as_ptr().cast()),
+        );
+    }
+}
+
+#[cfg(not(feature = "debug"))]
+#[inline(always)]
+unsafe fn set_debug_name<T: Interface>(_object: &T, _name: &str) {}
+
+/// A callback of the application, which can't be printed.
+struct Callback<F: ?Sized>(Box<F>);
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback(..)")
+    }
+}
+
+/// The state threaded through the draw calls of a render call.
+struct DrawState {
+    last_tex: TextureId,
+    stats: RenderStats,
+}
+
+impl DrawState {
+    // `setup_render_state` has just bound the font texture, so the tracking
+    // starts from it on every call regardless of what a previous call or the
+    // application left bound.
+    fn new(vtx_count: usize, idx_count: usize) -> Self {
+        DrawState {
+            last_tex: TextureId::from(FONT_TEX_ID),
+            stats: RenderStats {
+                total_vertices: vtx_count as u32,
+                total_indices: idx_count as u32,
+                ..RenderStats::default()
+            },
+        }
+    }
+}
+
+/// The number of render calls whose timestamp queries are in flight before
+/// the oldest ones are read back.
+const GPU_TIMER_LATENCY: usize = 3;
+
+/// The timestamp queries around a single render call.
+#[derive(Debug)]
+struct TimerQueries {
+    disjoint: ID3D11Query,
+    begin: ID3D11Query,
+    end: ID3D11Query,
+}
+
+/// A ring of timestamp queries measuring the GPU time of render calls.
+#[derive(Debug)]
+struct GpuTimer {
+    queries: [TimerQueries; GPU_TIMER_LATENCY],
+    issued: [bool; GPU_TIMER_LATENCY],
+    next: usize,
+    last_time: Option<f32>,
+}
+
+impl GpuTimer {
+    unsafe fn new(device: &ID3D11Device) -> Result<Self> {
+        let create_query = |query, name| -> Result<ID3D11Query> {
+            let desc = D3D11_QUERY_DESC { Query: query, MiscFlags: 0 };
+            let mut uninit_query = None;
+            device.CreateQuery(&desc, Some(&mut uninit_query))?;
+            let query = uninit_query.unwrap();
+            set_debug_name(&query, name);
+            Ok(query)
+        };
+        let create_queries = || -> Result<TimerQueries> {
+            Ok(TimerQueries {
+                disjoint: create_query(
+                    D3D11_QUERY_TIMESTAMP_DISJOINT,
+                    "imgui-dx11 timestamp disjoint query",
+                )?,
+                begin: create_query(D3D11_QUERY_TIMESTAMP, "imgui-dx11 begin timestamp query")?,
+                end: create_query(D3D11_QUERY_TIMESTAMP, "imgui-dx11 end timestamp query")?,
+            })
+        };
+        Ok(GpuTimer {
+            queries: [create_queries()?, create_queries()?, create_queries()?],
+            issued: [false; GPU_TIMER_LATENCY],
+            next: 0,
+            last_time: None,
+        })
     }
 
-    unsafe fn create_pixel_shader(device: &ID3D11Device) -> Result<ID3D11PixelShader> {
-        const PIXEL_SHADER: &[u8] =
-            include_bytes!(concat!(env!("OUT_DIR"), "/pixel_shader.ps_4_0"));
-        let mut uninit_pixel_shader = None;
-        device.CreatePixelShader(PIXEL_SHADER, None, Some(&mut uninit_pixel_shader))?;
-        Ok(uninit_pixel_shader.unwrap())
+    /// Reads back the queries about to be reused, then starts timing with them.
+    unsafe fn begin(&mut self, context: &ID3D11DeviceContext) {
+        if self.issued[self.next] {
+            self.resolve(self.next, context);
+        }
+        let queries = &self.queries[self.next];
+        context.Begin(&queries.disjoint);
+        context.End(&queries.begin);
     }
 
-    unsafe fn create_device_objects(
-        device: &ID3D11Device,
-    ) -> Result<(ID3D11BlendState, ID3D11RasterizerState, ID3D11DepthStencilState)> {
-        let desc = D3D11_BLEND_DESC {
-            AlphaToCoverageEnable: false.into(),
-            IndependentBlendEnable: true.into(),
-            RenderTarget: [D3D11_RENDER_TARGET_BLEND_DESC {
-                BlendEnable: true.into(),
-                SrcBlend: D3D11_BLEND_SRC_ALPHA,
-                DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
-                BlendOp: D3D11_BLEND_OP_ADD,
-                SrcBlendAlpha: D3D11_BLEND_ONE,
-                DestBlendAlpha: D3D11_BLEND_INV_SRC_ALPHA,
-                BlendOpAlpha: D3D11_BLEND_OP_ADD,
-                RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
-            }; 8],
-        };
+    unsafe fn end(&mut self, context: &ID3D11DeviceContext) {
+        let queries = &self.queries[self.next];
+        context.End(&queries.end);
+        context.End(&queries.disjoint);
+        self.issued[self.next] = true;
+        self.next = (self.next + 1) % GPU_TIMER_LATENCY;
+    }
 
-        let mut uninit_blend_state = None;
-        device.CreateBlendState(&desc, Some(&mut uninit_blend_state))?;
+    /// Updates `last_time` from the queries at `index` if their data is
+    /// available, which is skipped otherwise.
+    unsafe fn resolve(&mut self, index: usize, context: &ID3D11DeviceContext) {
+        let queries = &self.queries[index];
+        // `GetData` returns `S_FALSE` while the data isn't available, which is
+        // a success code as well, so the frequency is checked for having been
+        // written instead.
+        let mut disjoint = D3D11_QUERY_DATA_TIMESTAMP_DISJOINT::default();
+        let disjoint_ptr: *mut D3D11_QUERY_DATA_TIMESTAMP_DISJOINT = &mut disjoint;
+        let result = context.GetData(
+            &queries.disjoint,
+            Some(disjoint_ptr.cast()),
+            mem::size_of::<D3D11_QUERY_DATA_TIMESTAMP_DISJOINT>() as u32,
+            0,
+        );
+        if result.is_err() || disjoint.Frequency == 0 {
+            return;
+        }
+        if disjoint.Disjoint.as_bool() {
+            self.last_time = None;
+            return;
+        }
 
-        let desc = D3D11_RASTERIZER_DESC {
-            FillMode: D3D11_FILL_SOLID,
-            CullMode: D3D11_CULL_NONE,
-            DepthClipEnable: true.into(),
-            ScissorEnable: true.into(),
-            ..Default::default()
-        };
-        let mut uninit_rasterizer_state = None;
-        device.CreateRasterizerState(&desc, Some(&mut uninit_rasterizer_state))?;
+        // The timestamps were ended before the disjoint query, so they are
+        // available once it is.
+        let (mut begin, mut end) = (0u64, 0u64);
+        let (begin_ptr, end_ptr): (*mut u64, *mut u64) = (&mut begin, &mut end);
+        let size = mem::size_of::<u64>() as u32;
+        if context.GetData(&queries.begin, Some(begin_ptr.cast()), size, 0).is_err()
+            || context.GetData(&queries.end, Some(end_ptr.cast()), size, 0).is_err()
+        {
+            return;
+        }
+        let ticks = end.saturating_sub(begin);
+        self.last_time = Some((ticks as f64 * 1000.0 / disjoint.Frequency as f64) as f32);
+    }
+}
 
-        let stencil_op_desc = D3D11_DEPTH_STENCILOP_DESC {
-            StencilFailOp: D3D11_STENCIL_OP_KEEP,
-            StencilDepthFailOp: D3D11_STENCIL_OP_KEEP,
-            StencilPassOp: D3D11_STENCIL_OP_KEEP,
-            StencilFunc: D3D11_COMPARISON_ALWAYS,
-        };
-        let desc = D3D11_DEPTH_STENCIL_DESC {
-            DepthEnable: false.into(),
-            DepthWriteMask: D3D11_DEPTH_WRITE_MASK_ALL,
-            DepthFunc: D3D11_COMPARISON_ALWAYS,
-            StencilEnable: false.into(),
-            StencilReadMask: 0,
-            StencilWriteMask: 0,
-            FrontFace: stencil_op_desc,
-            BackFace: stencil_op_desc,
-        };
-        let mut uninit_depth_stencil_state = None;
-        device.CreateDepthStencilState(&desc, Some(&mut uninit_depth_stencil_state))?;
-        Ok((
-            uninit_blend_state.unwrap(),
-            uninit_rasterizer_state.unwrap(),
-            uninit_depth_stencil_state.unwrap(),
-        ))
+/// The device context calls issued while drawing.
+///
+/// This keeps the state setup, batching, texture switching and scissor logic
+/// independent of a live device context, so that the calls can be recorded
+/// instead.
+trait RenderBackend {
+    unsafe fn setup_render_state(&self, state: &RenderState<'_>);
+    unsafe fn set_texture(&self, view: &ID3D11ShaderResourceView);
+    unsafe fn set_sampler(&self, sampler: &ID3D11SamplerState);
+    unsafe fn set_scissor_rect(&self, rect: RECT);
+    unsafe fn draw_indexed(&self, index_count: u32, start_index: u32, base_vertex: i32);
+}
+
+impl RenderBackend for ID3D11DeviceContext {
+    unsafe fn setup_render_state(&self, state: &RenderState<'_>) {
+        let blend_factor = [0.0; 4];
+
+        self.RSSetViewports(Some(slice::from_ref(&state.viewport)));
+        self.IASetInputLayout(state.input_layout);
+        self.IASetVertexBuffers(
+            0,
+            1,
+            Some(state.vertex_buffer),
+            Some(&state.vertex_stride),
+            Some(&0),
+        );
+        self.IASetIndexBuffer(state.index_buffer, INDEX_FORMAT, 0);
+        self.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+        self.VSSetShader(state.vertex_shader, None);
+        self.VSSetConstantBuffers(0, Some(slice::from_ref(state.vertex_constant_buffer)));
+        self.PSSetShader(state.pixel_shader, None);
+        self.PSSetConstantBuffers(0, Some(slice::from_ref(state.pixel_constant_buffer)));
+        self.PSSetShaderResources(0, Some(slice::from_ref(state.texture)));
+        self.PSSetSamplers(0, Some(slice::from_ref(state.sampler)));
+        if state.unbind_shader_stages {
+            self.GSSetShader(None, None);
+            self.HSSetShader(None, None);
+            self.DSSetShader(None, None);
+            self.CSSetShader(None, None);
+        }
+        self.OMSetBlendState(state.blend_state, Some(blend_factor.as_ptr()), 0xFFFFFFFF);
+        self.OMSetDepthStencilState(state.depth_stencil_state, 0);
+        self.RSSetState(state.rasterizer_state);
+    }
+    #[inline]
+    unsafe fn set_texture(&self, view: &ID3D11ShaderResourceView) {
+        self.PSSetShaderResources(0, Some(slice::from_ref(view)));
+    }
+    #[inline]
+    unsafe fn set_sampler(&self, sampler: &ID3D11SamplerState) {
+        self.PSSetSamplers(0, Some(slice::from_ref(sampler)));
+    }
+    #[inline]
+    unsafe fn set_scissor_rect(&self, rect: RECT) {
+        self.RSSetScissorRects(Some(&[rect]));
+    }
+    #[inline]
+    unsafe fn draw_indexed(&self, index_count: u32, start_index: u32, base_vertex: i32) {
+        self.DrawIndexed(index_count, start_index, base_vertex);
     }
 }
 
+/// The pipeline state bound before drawing and again after every reset or
+/// callback command, with the font texture bound to slot 0.
+struct RenderState<'a> {
+    viewport: D3D11_VIEWPORT,
+    input_layout: &'a ID3D11InputLayout,
+    vertex_buffer: &'a Option<ID3D11Buffer>,
+    vertex_stride: u32,
+    index_buffer: &'a ID3D11Buffer,
+    vertex_shader: &'a ID3D11VertexShader,
+    vertex_constant_buffer: &'a ID3D11Buffer,
+    pixel_shader: &'a ID3D11PixelShader,
+    pixel_constant_buffer: &'a ID3D11Buffer,
+    texture: &'a ID3D11ShaderResourceView,
+    sampler: &'a ID3D11SamplerState,
+    unbind_shader_stages: bool,
+    blend_state: &'a ID3D11BlendState,
+    depth_stencil_state: &'a ID3D11DepthStencilState,
+    rasterizer_state: &'a ID3D11RasterizerState,
+}
+
+/// A run of consecutive draw commands that can be issued as one draw call.
+struct DrawBatch {
+    texture_id: TextureId,
+    clip_rect: [f32; 4],
+    vtx_offset: usize,
+    index_offset: usize,
+    count: usize,
+}
+
+// The buffer is stored as an `Option` as that is what `IASetVertexBuffers`
+// expects, sparing us a clone per frame. It is always `Some`.
 #[derive(Debug)]
-struct Buffer(ID3D11Buffer, usize);
+struct Buffer(Option<ID3D11Buffer>, usize);
 
 impl Buffer {
     #[inline]
@@ -518,18 +3466,28 @@ impl Buffer {
     }
     #[inline]
     fn get_buf(&self) -> &ID3D11Buffer {
+        self.0.as_ref().unwrap()
+    }
+    #[inline]
+    fn as_option(&self) -> &Option<ID3D11Buffer> {
         &self.0
     }
 }
 
+/// A backup of the device context state that imgui rendering modifies.
+///
+/// The renderer keeps a single instance around so that the allocations for
+/// the backed up slots are reused across frames.
 #[derive(Debug, Default)]
 struct StateBackup {
     context: Option<ID3D11DeviceContext>,
+    render_targets: [Option<ID3D11RenderTargetView>; 8],
+    depth_stencil_view: Option<ID3D11DepthStencilView>,
     scissor_rects: RECT,
     viewports: D3D11_VIEWPORT,
     rasterizer_state: Option<ID3D11RasterizerState>,
     blend_state: Option<ID3D11BlendState>,
-    blend_factor: f32,
+    blend_factor: [f32; 4],
     sample_mask: u32,
     depth_stencil_state: Option<ID3D11DepthStencilState>,
     stencil_ref: u32,
@@ -540,6 +3498,7 @@ struct StateBackup {
     vs_shader: Option<ID3D11VertexShader>,
     vs_instances: Option<ID3D11ClassInstance>,
     constant_buffer: Vec<Option<ID3D11Buffer>>,
+    ps_constant_buffer: Vec<Option<ID3D11Buffer>>,
     gs_shader: Option<ID3D11GeometryShader>,
     gs_instances: Option<ID3D11ClassInstance>,
     index_buffer: Option<ID3D11Buffer>,
@@ -553,62 +3512,99 @@ struct StateBackup {
 }
 
 impl StateBackup {
-    unsafe fn backup(context: Option<ID3D11DeviceContext>) -> Self {
-        let mut result = Self::default();
-        let ctx = context.as_ref().unwrap();
-        result.topology = ctx.IAGetPrimitiveTopology();
+    /// Backs up the state of `context` into `self`, restoring it once the
+    /// returned guard is dropped.
+    ///
+    /// Without a context nothing is backed up and the guard restores nothing.
+    unsafe fn backup(&mut self, context: Option<ID3D11DeviceContext>) -> StateGuard<'_> {
+        self.save(context);
+        StateGuard(self)
+    }
+
+    /// Backs up the state of `context` into `self`, to be restored explicitly
+    /// via [`StateBackup::restore`].
+    unsafe fn save(&mut self, context: Option<ID3D11DeviceContext>) {
+        let Some(ctx) = context.as_ref() else {
+            self.context = None;
+            return;
+        };
+        self.topology = ctx.IAGetPrimitiveTopology();
         ctx.IAGetIndexBuffer(
-            Some(&mut result.index_buffer),
-            Some(&mut result.index_buffer_format),
-            Some(&mut result.index_buffer_offset),
+            Some(&mut self.index_buffer),
+            Some(&mut self.index_buffer_format),
+            Some(&mut self.index_buffer_offset),
         );
         ctx.IAGetVertexBuffers(
             0,
             1,
-            Some(&mut result.vertex_buffer),
-            Some(&mut result.vertex_buffer_stride),
-            Some(&mut result.vertex_buffer_offset),
+            Some(&mut self.vertex_buffer),
+            Some(&mut self.vertex_buffer_stride),
+            Some(&mut self.vertex_buffer_offset),
         );
-        result.input_layout = ctx.IAGetInputLayout().ok();
-        ctx.VSGetShader(&mut result.vs_shader, Some(&mut result.vs_instances), Some(&mut 256));
-        ctx.VSGetConstantBuffers(0, Some(&mut result.constant_buffer));
-        ctx.GSGetShader(&mut result.gs_shader, Some(&mut result.gs_instances), Some(&mut 256));
-        ctx.RSGetViewports(&mut 1, Some(&mut result.viewports));
-        ctx.RSGetScissorRects(&mut 1, Some(&mut result.scissor_rects));
-        result.rasterizer_state = ctx.RSGetState().ok();
-        ctx.PSGetShaderResources(0, Some(&mut result.shader_resource));
-        ctx.PSGetSamplers(0, Some(&mut result.sampler));
-        ctx.PSGetShader(&mut result.ps_shader, Some(&mut result.ps_instances), Some(&mut 256));
+        self.input_layout = ctx.IAGetInputLayout().ok();
+        ctx.VSGetShader(&mut self.vs_shader, Some(&mut self.vs_instances), Some(&mut 256));
+        self.constant_buffer.resize(1, None);
+        ctx.VSGetConstantBuffers(0, Some(&mut self.constant_buffer));
+        ctx.GSGetShader(&mut self.gs_shader, Some(&mut self.gs_instances), Some(&mut 256));
+        ctx.RSGetViewports(&mut 1, Some(&mut self.viewports));
+        ctx.RSGetScissorRects(&mut 1, Some(&mut self.scissor_rects));
+        self.rasterizer_state = ctx.RSGetState().ok();
+        self.shader_resource.resize(1, None);
+        ctx.PSGetShaderResources(0, Some(&mut self.shader_resource));
+        self.sampler.resize(1, None);
+        ctx.PSGetSamplers(0, Some(&mut self.sampler));
+        self.ps_constant_buffer.resize(1, None);
+        ctx.PSGetConstantBuffers(0, Some(&mut self.ps_constant_buffer));
+        ctx.PSGetShader(&mut self.ps_shader, Some(&mut self.ps_instances), Some(&mut 256));
         ctx.OMGetBlendState(
-            Some(&mut result.blend_state),
-            Some(&mut result.blend_factor),
-            Some(&mut result.sample_mask),
+            Some(&mut self.blend_state),
+            Some(self.blend_factor.as_mut_ptr()),
+            Some(&mut self.sample_mask),
         );
         ctx.OMGetDepthStencilState(
-            Some(&mut result.depth_stencil_state),
-            Some(&mut result.stencil_ref),
+            Some(&mut self.depth_stencil_state),
+            Some(&mut self.stencil_ref),
         );
-        result.context = context;
-        result
-    }
-
-    fn filter_none<T: Clone>(option_vec: &Vec<Option<T>>) -> Vec<T> {
-        option_vec.iter().filter_map(|x| x.as_ref().cloned()).collect::<Vec<_>>()
+        ctx.OMGetRenderTargets(Some(&mut self.render_targets), Some(&mut self.depth_stencil_view));
+        self.context = context;
     }
 
     pub fn restore(&mut self) {
         unsafe {
-            if self.context.is_none() { return };
+            if self.context.is_none() {
+                log_warn!("restoring device context state without a backup");
+                return;
+            }
 
             let ctx = self.context.as_ref().unwrap();
 
             ctx.RSSetScissorRects(Some(&[self.scissor_rects]));
             ctx.RSSetViewports(Some(&[self.viewports]));
+            // Without any states bound the getters return `None`, which binds
+            // the default states again. The blend factor and sample mask are
+            // reported as their defaults then, `[1.0; 4]` and `0xFFFFFFFF`.
             ctx.RSSetState(self.rasterizer_state.as_ref());
-            ctx.OMSetBlendState(self.blend_state.as_ref(), Some(&self.blend_factor), 0xFFFFFFFF);
+            ctx.OMSetBlendState(
+                self.blend_state.as_ref(),
+                Some(self.blend_factor.as_ptr()),
+                self.sample_mask,
+            );
             ctx.OMSetDepthStencilState(self.depth_stencil_state.as_ref(), self.stencil_ref);
-            ctx.PSSetShaderResources(0, Some(&Self::filter_none(&self.shader_resource)));
-            ctx.PSSetSamplers(0, Some(&Self::filter_none(&self.sampler)));
+            for (slot, view) in self.shader_resource.iter().enumerate() {
+                if let Some(view) = view {
+                    ctx.PSSetShaderResources(slot as u32, Some(slice::from_ref(view)));
+                }
+            }
+            for (slot, sampler) in self.sampler.iter().enumerate() {
+                if let Some(sampler) = sampler {
+                    ctx.PSSetSamplers(slot as u32, Some(slice::from_ref(sampler)));
+                }
+            }
+            for (slot, buffer) in self.ps_constant_buffer.iter().enumerate() {
+                if let Some(buffer) = buffer {
+                    ctx.PSSetConstantBuffers(slot as u32, Some(slice::from_ref(buffer)));
+                }
+            }
             ctx.PSSetShader(
                 self.ps_shader.as_ref(),
                 self.ps_instances.as_ref().map(slice::from_ref),
@@ -617,12 +3613,16 @@ impl StateBackup {
                 self.vs_shader.as_ref(),
                 self.vs_instances.as_ref().map(slice::from_ref),
             );
-            ctx.VSSetConstantBuffers(0, Some(&Self::filter_none(&self.constant_buffer)));
+            for (slot, buffer) in self.constant_buffer.iter().enumerate() {
+                if let Some(buffer) = buffer {
+                    ctx.VSSetConstantBuffers(slot as u32, Some(slice::from_ref(buffer)));
+                }
+            }
             ctx.GSSetShader(
                 self.gs_shader.as_ref(),
                 self.gs_instances.as_ref().map(slice::from_ref),
             );
-            ctx.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            ctx.IASetPrimitiveTopology(self.topology);
             ctx.IASetIndexBuffer(
                 self.index_buffer.as_ref(),
                 self.index_buffer_format,
@@ -636,12 +3636,862 @@ impl StateBackup {
                 Some(&self.vertex_buffer_offset),
             );
             ctx.IASetInputLayout(self.input_layout.as_ref());
+            // `OMSetRenderTargets` only takes bound views, which would shift
+            // sparse bindings like slots 0 and 2 into slots 0 and 1. All slots
+            // are passed as backed up instead, unbound ones as null pointers.
+            (Vtable::vtable(ctx).OMSetRenderTargets)(
+                Vtable::as_raw(ctx),
+                self.render_targets.len() as u32,
+                self.render_targets.as_ptr().cast(),
+                self.depth_stencil_view.as_ref().map_or(ptr::null_mut(), Vtable::as_raw),
+            );
         }
+        self.release();
+    }
+
+    /// Drops all references to the backed up state, keeping the allocations
+    /// around for the next backup.
+    fn release(&mut self) {
+        let mut shader_resource = mem::take(&mut self.shader_resource);
+        let mut sampler = mem::take(&mut self.sampler);
+        let mut constant_buffer = mem::take(&mut self.constant_buffer);
+        let mut ps_constant_buffer = mem::take(&mut self.ps_constant_buffer);
+        shader_resource.clear();
+        sampler.clear();
+        constant_buffer.clear();
+        ps_constant_buffer.clear();
+        *self = StateBackup {
+            shader_resource,
+            sampler,
+            constant_buffer,
+            ps_constant_buffer,
+            ..Default::default()
+        };
     }
 }
 
-impl Drop for StateBackup {
+/// Restores the backed up state on drop.
+struct StateGuard<'a>(&'a mut StateBackup);
+
+impl Drop for StateGuard<'_> {
     fn drop(&mut self) {
-        self.restore();
+        self.0.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::cell::Cell;
+    use std::sync::{Mutex, MutexGuard};
+    use std::vec;
+
+    use imgui::sys;
+
+    use super::*;
+
+    // imgui allows a single active context, while tests run in parallel.
+    static IMGUI_LOCK: Mutex<()> = Mutex::new(());
+
+    struct Harness {
+        renderer: Renderer,
+        imgui: imgui::Context,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let lock = IMGUI_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut imgui = imgui::Context::create();
+            imgui.set_ini_filename(None);
+            imgui.io_mut().display_size = [100.0, 100.0];
+            let device = create_warp_device().unwrap();
+            let renderer = Renderer::new(&mut imgui, &device).unwrap();
+            Harness { renderer, imgui, _lock: lock }
+        }
+
+        fn frame(&mut self, f: impl FnOnce(&imgui::Ui)) -> (&mut Renderer, &DrawData) {
+            f(self.imgui.new_frame());
+            (&mut self.renderer, self.imgui.render())
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        SetupRenderState { viewport: [f32; 4] },
+        SetTexture(ID3D11ShaderResourceView),
+        SetSampler(ID3D11SamplerState),
+        SetScissorRect(RECT),
+        DrawIndexed { index_count: u32, start_index: u32, base_vertex: i32 },
+    }
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<Call>>);
+
+    impl RenderBackend for Recorder {
+        unsafe fn setup_render_state(&self, state: &RenderState<'_>) {
+            let viewport = state.viewport;
+            let viewport = [viewport.TopLeftX, viewport.TopLeftY, viewport.Width, viewport.Height];
+            self.0.borrow_mut().push(Call::SetupRenderState { viewport });
+        }
+        unsafe fn set_texture(&self, view: &ID3D11ShaderResourceView) {
+            self.0.borrow_mut().push(Call::SetTexture(view.clone()));
+        }
+        unsafe fn set_sampler(&self, sampler: &ID3D11SamplerState) {
+            self.0.borrow_mut().push(Call::SetSampler(sampler.clone()));
+        }
+        unsafe fn set_scissor_rect(&self, rect: RECT) {
+            self.0.borrow_mut().push(Call::SetScissorRect(rect));
+        }
+        unsafe fn draw_indexed(&self, index_count: u32, start_index: u32, base_vertex: i32) {
+            self.0.borrow_mut().push(Call::DrawIndexed { index_count, start_index, base_vertex });
+        }
+    }
+
+    /// Records the calls `render` would issue for `draw_data`.
+    fn record(renderer: &Renderer, draw_data: &DrawData) -> Vec<Call> {
+        let recorder = Recorder::default();
+        let viewport = renderer.draw_data_viewport(draw_data);
+        unsafe {
+            renderer.setup_render_state(&recorder, &viewport);
+            renderer.render_impl(&recorder, draw_data, &viewport).unwrap();
+        }
+        recorder.0.into_inner()
+    }
+
+    fn scissor(left: i32, top: i32, right: i32, bottom: i32) -> Call {
+        Call::SetScissorRect(RECT { left, top, right, bottom })
+    }
+
+    const WHITE: [f32; 4] = [1.0; 4];
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    #[test]
+    fn records_calls_of_several_draw_lists() {
+        let mut harness = Harness::new();
+        let texture = harness.renderer.register_rgba8_texture(1, 1, &[255; 4]).unwrap();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let background = ui.get_background_draw_list();
+            background.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            background.add_image(texture, [10.0, 10.0], [20.0, 20.0]).build();
+            drop(background);
+            let foreground = ui.get_foreground_draw_list();
+            foreground.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            foreground.with_clip_rect([0.0, 0.0], [50.0, 50.0], || {
+                foreground.add_rect([20.0, 20.0], [30.0, 30.0], WHITE).filled(true).build();
+            });
+        });
+        assert_eq!(draw_data.draw_lists_count(), 2);
+
+        let font = renderer.font_texture_view().clone();
+        let view = renderer.textures.get(texture).unwrap().clone();
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+                Call::SetTexture(view),
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 6, base_vertex: 0 },
+                Call::SetTexture(font),
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 12, base_vertex: 8 },
+                scissor(0, 0, 50, 50),
+                Call::DrawIndexed { index_count: 6, start_index: 18, base_vertex: 8 },
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_base_vertex_by_preceding_draw_lists() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let background = ui.get_background_draw_list();
+            for x in [0.0, 20.0, 40.0] {
+                background.add_rect([x, 0.0], [x + 10.0, 10.0], WHITE).filled(true).build();
+            }
+            drop(background);
+            let foreground = ui.get_foreground_draw_list();
+            for x in [0.0, 20.0] {
+                foreground.add_rect([x, 20.0], [x + 10.0, 30.0], WHITE).filled(true).build();
+            }
+        });
+
+        let draws: Vec<_> = record(renderer, draw_data)
+            .into_iter()
+            .filter(|call| matches!(call, Call::DrawIndexed { .. }))
+            .collect();
+        assert_eq!(
+            draws,
+            vec![
+                Call::DrawIndexed { index_count: 18, start_index: 0, base_vertex: 0 },
+                Call::DrawIndexed { index_count: 12, start_index: 18, base_vertex: 12 },
+            ]
+        );
+        // Rendering on the device checks the base vertex range of every list.
+        renderer.render_to_texture(draw_data, 100, 100).unwrap();
+        assert_eq!(renderer.last_draw_list_count, 2);
+        assert_eq!(renderer.last_stats.draw_calls, 2);
+    }
+
+    #[test]
+    fn intersects_scissor_rects_with_bound_viewport() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let foreground = ui.get_foreground_draw_list();
+            foreground.with_clip_rect([-50.0, -50.0], [200.0, 200.0], || {
+                foreground.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            });
+        });
+        let scissors = |renderer: &Renderer| -> Vec<_> {
+            record(renderer, draw_data)
+                .into_iter()
+                .filter(|call| matches!(call, Call::SetScissorRect(_)))
+                .collect()
+        };
+
+        assert_eq!(scissors(renderer), vec![scissor(0, 0, 100, 100)]);
+        renderer.set_render_viewport(Some(D3D11_VIEWPORT {
+            TopLeftX: 20.0,
+            TopLeftY: 30.0,
+            Width: 50.0,
+            Height: 40.0,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        }));
+        assert_eq!(scissors(renderer), vec![scissor(20, 30, 70, 70)]);
+        renderer.set_render_viewport(None);
+        renderer.set_viewport_offset([10.5, -5.5]);
+        assert_eq!(scissors(renderer), vec![scissor(10, -6, 111, 95)]);
+    }
+
+    #[test]
+    fn skips_draw_data_with_zero_framebuffer_scale() {
+        let mut harness = Harness::new();
+        harness.imgui.io_mut().display_framebuffer_scale = [0.0, 0.0];
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        assert_eq!(draw_data.framebuffer_scale, [0.0, 0.0]);
+
+        renderer.render(draw_data).unwrap();
+        assert_eq!(renderer.last_draw_list_count, 0);
+        assert_eq!(renderer.last_stats.draw_calls, 0);
+    }
+
+    #[test]
+    fn skips_commands_clipped_off_screen() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let foreground = ui.get_foreground_draw_list();
+            foreground.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            foreground.with_clip_rect([150.0, 150.0], [200.0, 200.0], || {
+                foreground.add_rect([150.0, 150.0], [160.0, 160.0], WHITE).filled(true).build();
+            });
+        });
+        assert_eq!(draw_data.total_idx_count, 12);
+
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_empty_draw_data() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|_| {});
+        assert_eq!(draw_data.total_vtx_count, 0);
+
+        renderer.render(draw_data).unwrap();
+        assert_eq!(renderer.last_draw_list_count, 0);
+        assert_eq!(renderer.last_stats, RenderStats::default());
+    }
+
+    /// Reads back the RGBA8 pixel at `x`, `y` of a texture via a staging copy.
+    unsafe fn read_pixel(
+        renderer: &Renderer,
+        view: &ID3D11ShaderResourceView,
+        x: u32,
+        y: u32,
+    ) -> [u8; 4] {
+        let mut resource = None;
+        view.GetResource(&mut resource);
+        let texture = resource.unwrap().cast::<ID3D11Texture2D>().unwrap();
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        texture.GetDesc(&mut desc);
+        let desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: D3D11_BIND_FLAG::default(),
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+            ..desc
+        };
+        let mut staging = None;
+        renderer.device.CreateTexture2D(&desc, None, Some(&mut staging)).unwrap();
+        let staging = staging.unwrap();
+        renderer.context.CopyResource(&staging, &texture);
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        renderer.context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped)).unwrap();
+        let offset = (y * mapped.RowPitch + x * 4) as usize;
+        let pixel = *mapped.pData.cast::<u8>().add(offset).cast::<[u8; 4]>();
+        renderer.context.Unmap(&staging, 0);
+        pixel
+    }
+
+    #[test]
+    fn renders_draw_lists_exceeding_the_index_range() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let background = ui.get_background_draw_list();
+            for _ in 0..20_000 {
+                background.add_rect([0.0, 0.0], [1.0, 1.0], WHITE).filled(true).build();
+            }
+            background.add_rect([50.0, 50.0], [100.0, 100.0], RED).filled(true).build();
+        });
+        // The indices have to be uploaded unchanged in whichever width imgui
+        // was built with. With 16 bit indices imgui additionally starts
+        // commands with a vertex offset past their range.
+        assert!(draw_data.total_vtx_count as usize > usize::from(u16::MAX));
+
+        let view = renderer.render_to_texture(draw_data, 100, 100).unwrap();
+        let index_buffer = &renderer.objects().index_buffer;
+        let mut desc = D3D11_BUFFER_DESC::default();
+        unsafe { index_buffer.get_buf().GetDesc(&mut desc) };
+        assert_eq!(desc.ByteWidth as usize, index_buffer.len() * mem::size_of::<DrawIdx>());
+        let indices: Vec<DrawIdx> = unsafe {
+            read_buffer_slice(renderer, index_buffer.get_buf(), draw_data.total_idx_count as usize)
+        };
+        let expected: Vec<DrawIdx> =
+            draw_data.draw_lists().flat_map(|list| list.idx_buffer().iter().copied()).collect();
+        assert_eq!(indices, expected);
+        assert_eq!(unsafe { read_pixel(renderer, &view, 75, 75) }, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_buffer_sizes_overflowing_u32() {
+        assert_eq!(buffer_byte_width(1000, 20).unwrap(), 20_000);
+        let too_large = u32::MAX as usize / 20 + 1;
+        assert!(matches!(
+            buffer_byte_width(too_large, 20),
+            Err(RendererError::BufferTooLarge { len }) if len == too_large
+        ));
+        assert!(matches!(
+            buffer_byte_width(usize::MAX, 2),
+            Err(RendererError::BufferTooLarge { .. })
+        ));
+        assert!(matches!(buffer_len(usize::MAX, 1), Err(RendererError::BufferTooLarge { .. })));
+
+        let mut harness = Harness::new();
+        assert!(matches!(
+            harness.renderer.reserve(too_large, 0),
+            Err(RendererError::BufferTooLarge { .. })
+        ));
+    }
+
+    /// A callback pointer with the given sentinel value, for callback commands
+    /// imgui-rs has no API to add.
+    fn sentinel_callback(value: isize) -> sys::ImDrawCallback {
+        unsafe { mem::transmute::<isize, sys::ImDrawCallback>(value) }
+    }
+
+    #[test]
+    fn rebinds_textures_after_reset_render_state() {
+        let mut harness = Harness::new();
+        let texture = harness.renderer.register_rgba8_texture(1, 1, &[255; 4]).unwrap();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let background = ui.get_background_draw_list();
+            background.add_image(texture, [0.0, 0.0], [10.0, 10.0]).build();
+            unsafe {
+                sys::ImDrawList_AddCallback(
+                    sys::igGetBackgroundDrawList_Nil(),
+                    sentinel_callback(-1),
+                    ptr::null_mut(),
+                );
+            }
+            background.add_image(texture, [10.0, 10.0], [20.0, 20.0]).build();
+        });
+
+        let view = renderer.textures.get(texture).unwrap().clone();
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                Call::SetTexture(view.clone()),
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                Call::SetTexture(view),
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 6, base_vertex: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn binds_leading_textures_of_consecutive_renders() {
+        let mut harness = Harness::new();
+        let first = harness.renderer.register_rgba8_texture(1, 1, &[255; 4]).unwrap();
+        let second = harness.renderer.register_rgba8_texture(1, 1, &[0; 4]).unwrap();
+        for texture in [first, second, first] {
+            let (renderer, draw_data) = harness.frame(|ui| {
+                ui.get_background_draw_list().add_image(texture, [0.0; 2], [10.0; 2]).build();
+            });
+            renderer.render(draw_data).unwrap();
+            assert_eq!(renderer.last_stats.texture_binds, 1);
+
+            let view = renderer.textures.get(texture).unwrap().clone();
+            assert_eq!(
+                record(renderer, draw_data),
+                vec![
+                    Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                    Call::SetTexture(view),
+                    scissor(0, 0, 100, 100),
+                    Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reserve_grows_buffers_ahead_of_rendering() {
+        let mut harness = Harness::new();
+        harness.renderer.reserve(50_000, 100_000).unwrap();
+        let objects = harness.renderer.objects();
+        assert!(objects.vertex_buffer.len() >= 50_000);
+        assert!(objects.index_buffer.len() >= 100_000);
+        let vertex_buffer = objects.vertex_buffer.get_buf().clone();
+        let index_buffer = objects.index_buffer.get_buf().clone();
+
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        renderer.render(draw_data).unwrap();
+        renderer.reserve(10, 10).unwrap();
+        assert_eq!(renderer.objects().vertex_buffer.get_buf(), &vertex_buffer);
+        assert_eq!(renderer.objects().index_buffer.get_buf(), &index_buffer);
+    }
+
+    /// Reads back the start of a buffer via a staging copy.
+    unsafe fn read_buffer<T: Copy>(renderer: &Renderer, buffer: &ID3D11Buffer) -> T {
+        read_buffer_slice(renderer, buffer, 1)[0]
+    }
+
+    /// Reads back the first `len` elements of a buffer via a staging copy.
+    unsafe fn read_buffer_slice<T: Copy>(
+        renderer: &Renderer,
+        buffer: &ID3D11Buffer,
+        len: usize,
+    ) -> Vec<T> {
+        let mut desc = D3D11_BUFFER_DESC::default();
+        buffer.GetDesc(&mut desc);
+        let desc = D3D11_BUFFER_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: D3D11_BIND_FLAG::default(),
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+            ..desc
+        };
+        let mut staging = None;
+        renderer.device.CreateBuffer(&desc, None, Some(&mut staging)).unwrap();
+        let staging = staging.unwrap();
+        renderer.context.CopyResource(&staging, buffer);
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        renderer.context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped)).unwrap();
+        let values = slice::from_raw_parts(mapped.pData.cast::<T>(), len).to_vec();
+        renderer.context.Unmap(&staging, 0);
+        values
+    }
+
+    /// The projection last written to the vertex constant buffer.
+    fn read_mvp(renderer: &Renderer) -> [[f32; 4]; 4] {
+        unsafe { read_buffer(renderer, &renderer.objects().constant_buffer) }
+    }
+
+    #[test]
+    fn writes_depth_into_projection() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        renderer.render(draw_data).unwrap();
+        assert_eq!(read_mvp(renderer)[3][2], 0.5);
+
+        renderer.set_depth(0.0);
+        renderer.render(draw_data).unwrap();
+        assert_eq!(read_mvp(renderer)[3][2], 0.0);
+    }
+
+    #[test]
+    fn flips_projection_vertically() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        renderer.render(draw_data).unwrap();
+        let mvp = read_mvp(renderer);
+        assert!(mvp[1][1] < 0.0);
+
+        renderer.set_flip_y(true);
+        renderer.render(draw_data).unwrap();
+        let flipped = read_mvp(renderer);
+        assert_eq!(flipped[1][1], -mvp[1][1]);
+        assert_eq!(flipped[3][1], -mvp[3][1]);
+    }
+
+    #[test]
+    fn skips_rewriting_an_unchanged_projection() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        renderer.render(draw_data).unwrap();
+
+        // A marker written behind the renderer's back survives as long as the
+        // projection doesn't change.
+        let marker = [[7.0; 4]; 4];
+        unsafe {
+            let buffer = &renderer.objects().constant_buffer;
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            renderer.context.Map(buffer, 0, D3D11_MAP_WRITE_DISCARD, 0, Some(&mut mapped)).unwrap();
+            *mapped.pData.cast::<[[f32; 4]; 4]>() = marker;
+            renderer.context.Unmap(buffer, 0);
+        }
+        renderer.render(draw_data).unwrap();
+        assert_eq!(read_mvp(renderer), marker);
+
+        renderer.set_depth(0.0);
+        renderer.render(draw_data).unwrap();
+        assert_ne!(read_mvp(renderer), marker);
+    }
+
+    #[test]
+    fn rejects_textures_above_the_device_limit() {
+        let mut harness = Harness::new();
+        let result = harness.renderer.register_rgba8_texture(16385, 1, &vec![0; 16385 * 4]);
+        assert!(matches!(
+            result,
+            Err(RendererError::TextureTooLarge { width: 16385, height: 1, max: 16384 })
+        ));
+        assert!(harness.renderer.register_rgba8_texture(16384, 1, &vec![0; 16384 * 4]).is_ok());
+    }
+
+    #[test]
+    fn rejects_render_targets_above_the_device_limit() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|_| {});
+        assert!(matches!(
+            renderer.create_offscreen_targets(1, 16385),
+            Err(RendererError::TextureTooLarge { width: 1, height: 16385, max: 16384 })
+        ));
+        assert!(matches!(
+            renderer.render_to_texture(draw_data, 16385, 1),
+            Err(RendererError::TextureTooLarge { width: 16385, height: 1, max: 16384 })
+        ));
+    }
+
+    #[test]
+    fn blends_only_the_first_render_target() {
+        let harness = Harness::new();
+        let mut desc = D3D11_BLEND_DESC::default();
+        unsafe { harness.renderer.objects().blend_state.GetDesc(&mut desc) };
+        assert!(!desc.IndependentBlendEnable.as_bool());
+        let target = desc.RenderTarget[0];
+        assert!(target.BlendEnable.as_bool());
+        assert_eq!(target.SrcBlend, D3D11_BLEND_SRC_ALPHA);
+        assert_eq!(target.DestBlend, D3D11_BLEND_INV_SRC_ALPHA);
+        assert_eq!(target.SrcBlendAlpha, D3D11_BLEND_ONE);
+        assert_eq!(target.DestBlendAlpha, D3D11_BLEND_INV_SRC_ALPHA);
+        assert_eq!(target.RenderTargetWriteMask, D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8);
+    }
+
+    #[test]
+    fn backs_up_nothing_without_a_context() {
+        let mut backup = StateBackup::default();
+        drop(unsafe { backup.backup(None) });
+        assert!(backup.context.is_none());
+        backup.restore();
+    }
+
+    #[test]
+    fn restores_default_states_of_a_pristine_context() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        renderer.render(draw_data).unwrap();
+        assert_eq!(renderer.last_stats.draw_calls, 1);
+
+        let ctx = &renderer.context;
+        let mut blend_state = None;
+        let mut blend_factor = [0.0; 4];
+        let mut sample_mask = 0;
+        let mut depth_stencil_state = None;
+        let mut stencil_ref = 1;
+        let mut pixel_shader = None;
+        unsafe {
+            ctx.OMGetBlendState(
+                Some(&mut blend_state),
+                Some(blend_factor.as_mut_ptr()),
+                Some(&mut sample_mask),
+            );
+            ctx.OMGetDepthStencilState(Some(&mut depth_stencil_state), Some(&mut stencil_ref));
+            ctx.PSGetShader(&mut pixel_shader, None, None);
+            assert!(ctx.RSGetState().is_err());
+            assert_eq!(ctx.IAGetPrimitiveTopology(), D3D_PRIMITIVE_TOPOLOGY_UNDEFINED);
+        }
+        assert!(blend_state.is_none());
+        assert_eq!(blend_factor, [1.0; 4]);
+        assert_eq!(sample_mask, 0xFFFFFFFF);
+        assert!(depth_stencil_state.is_none());
+        assert_eq!(stencil_ref, 0);
+        assert!(pixel_shader.is_none());
+    }
+
+    #[test]
+    fn projection_matrix_matches_written_projection() {
+        let mut harness = Harness::new();
+        harness.imgui.io_mut().display_size = [200.0, 100.0];
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        let expected = [
+            [0.01, 0.0, 0.0, 0.0],
+            [0.0, -0.02, 0.0, 0.0],
+            [0.0, 0.0, 0.5, 0.0],
+            [-1.0, 1.0, 0.5, 1.0],
+        ];
+        assert_eq!(Renderer::projection_matrix(draw_data), expected);
+
+        renderer.render(draw_data).unwrap();
+        assert_eq!(read_mvp(renderer), expected);
+    }
+
+    #[test]
+    fn sets_up_render_state_after_raw_callbacks() {
+        unsafe extern "C" fn mark_called(_: *const sys::ImDrawList, cmd: *const sys::ImDrawCmd) {
+            (*(*cmd).UserCallbackData.cast::<Cell<bool>>()).set(true);
+        }
+
+        let called = Cell::new(false);
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let background = ui.get_background_draw_list();
+            background.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            unsafe {
+                sys::ImDrawList_AddCallback(
+                    sys::igGetBackgroundDrawList_Nil(),
+                    Some(mark_called),
+                    (&called as *const Cell<bool>).cast_mut().cast(),
+                );
+            }
+            background.add_rect([10.0, 10.0], [20.0, 20.0], WHITE).filled(true).build();
+        });
+
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 6, base_vertex: 0 },
+            ]
+        );
+        assert!(called.get());
+    }
+
+    #[test]
+    fn keeps_offsets_across_reset_render_state() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+            let foreground = ui.get_foreground_draw_list();
+            foreground.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            unsafe {
+                sys::ImDrawList_AddCallback(
+                    sys::igGetForegroundDrawList_Nil(),
+                    sentinel_callback(-1),
+                    ptr::null_mut(),
+                );
+            }
+            foreground.add_rect([10.0, 10.0], [20.0, 20.0], WHITE).filled(true).build();
+        });
+
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 6, base_vertex: 4 },
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 12, base_vertex: 4 },
+            ]
+        );
+    }
+
+    #[test]
+    fn never_calls_the_reset_render_state_sentinel() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let background = ui.get_background_draw_list();
+            background.add_rect([0.0, 0.0], [10.0, 10.0], WHITE).filled(true).build();
+            // Newer versions of imgui use `-8`, which imgui-rs may pass on as
+            // a raw callback. Calling it would crash.
+            unsafe {
+                sys::ImDrawList_AddCallback(
+                    sys::igGetBackgroundDrawList_Nil(),
+                    sentinel_callback(-8),
+                    ptr::null_mut(),
+                );
+            }
+            background.add_rect([10.0, 10.0], [20.0, 20.0], WHITE).filled(true).build();
+        });
+
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+                Call::SetupRenderState { viewport: [0.0, 0.0, 100.0, 100.0] },
+                scissor(0, 0, 100, 100),
+                Call::DrawIndexed { index_count: 6, start_index: 6, base_vertex: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_gamma_and_brightness_into_pixel_constants() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        renderer.set_gamma(2.2);
+        renderer.set_brightness(0.5);
+        renderer.render(draw_data).unwrap();
+
+        let constants: PixelConstantBuffer =
+            unsafe { read_buffer(renderer, &renderer.objects().pixel_constant_buffer) };
+        assert_eq!(constants.gamma, 2.2);
+        assert_eq!(constants.brightness, 0.5);
+        assert_eq!(constants.tint, [1.0; 4]);
+    }
+
+    #[test]
+    fn rounds_scissor_rects_outwards_at_fractional_scales() {
+        let mut harness = Harness::new();
+        harness.imgui.io_mut().display_framebuffer_scale = [1.5, 1.5];
+        let (renderer, draw_data) = harness.frame(|ui| {
+            let foreground = ui.get_foreground_draw_list();
+            foreground.with_clip_rect([3.0, 3.0], [20.3, 20.3], || {
+                foreground.add_rect([0.0, 0.0], [30.0, 30.0], WHITE).filled(true).build();
+            });
+        });
+
+        assert_eq!(
+            record(renderer, draw_data),
+            vec![
+                Call::SetupRenderState { viewport: [0.0, 0.0, 150.0, 150.0] },
+                scissor(4, 4, 31, 31),
+                Call::DrawIndexed { index_count: 6, start_index: 0, base_vertex: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn draws_wireframes_with_scissor_test() {
+        let mut harness = Harness::new();
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+        });
+        let fill_mode = |renderer: &mut Renderer| {
+            renderer.render_no_backup(draw_data).unwrap();
+            let mut desc = D3D11_RASTERIZER_DESC::default();
+            unsafe { renderer.context.RSGetState().unwrap().GetDesc(&mut desc) };
+            assert!(desc.ScissorEnable.as_bool());
+            desc.FillMode
+        };
+
+        assert_eq!(fill_mode(renderer), D3D11_FILL_SOLID);
+        renderer.set_wireframe(true);
+        assert_eq!(fill_mode(renderer), D3D11_FILL_WIREFRAME);
+    }
+
+    #[test]
+    fn rejects_vertex_strides_below_a_vertex() {
+        let mut harness = Harness::new();
+        let device = harness.renderer.device.clone();
+        let result =
+            unsafe { Renderer::builder().vertex_stride(4) }.build(&mut harness.imgui, &device);
+        assert!(matches!(
+            result,
+            Err(RendererError::InvalidVertexStride { stride: 4, min }) if min == mem::size_of::<DrawVert>()
+        ));
+    }
+
+    #[test]
+    fn keeps_replaced_ids_of_removed_textures() {
+        let mut harness = Harness::new();
+        let renderer = &mut harness.renderer;
+        let id = renderer.register_rgba8_texture(1, 1, &[255; 4]).unwrap();
+        let replacement = renderer.texture(id).unwrap().clone();
+        renderer.remove_texture(id);
+        assert!(renderer.replace_texture(id, replacement.clone()).is_none());
+
+        let other = renderer.register_rgba8_texture(1, 1, &[0; 4]).unwrap();
+        assert_ne!(other, id);
+        assert_eq!(renderer.texture(id), Some(&replacement));
+        assert_eq!(renderer.iter_textures().count(), 2);
+    }
+
+    #[test]
+    fn notifies_resets_of_either_sentinel() {
+        let resets = Rc::new(Cell::new(0));
+        let mut harness = Harness::new();
+        let counter = resets.clone();
+        harness
+            .renderer
+            .set_on_reset_render_state(Some(Box::new(move |_| counter.set(counter.get() + 1))));
+        let (renderer, draw_data) = harness.frame(|ui| {
+            ui.get_background_draw_list().add_rect([0.0; 2], [10.0; 2], WHITE).filled(true).build();
+            for value in RESET_RENDER_STATE_CALLBACKS {
+                unsafe {
+                    sys::ImDrawList_AddCallback(
+                        sys::igGetBackgroundDrawList_Nil(),
+                        sentinel_callback(value),
+                        ptr::null_mut(),
+                    );
+                }
+            }
+        });
+
+        record(renderer, draw_data);
+        assert_eq!(resets.get(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_compressed_mip_chains() {
+        let mut harness = Harness::new();
+        let renderer = &mut harness.renderer;
+        let data = [0; 8 * 4 * 4 + 8 * 4 + 8];
+        let bc1 = DxgiCompressedFormat::Bc1;
+        assert!(renderer.register_compressed_texture(16, 16, bc1, &data, &[0, 128, 160]).is_ok());
+        assert!(matches!(
+            renderer.register_compressed_texture(16, 16, bc1, &data, &[0; 33]),
+            Err(RendererError::TooManyMipLevels { levels: 33, max: 5 })
+        ));
+        assert!(matches!(
+            renderer.register_compressed_texture(16, 16, bc1, &data, &[usize::MAX]),
+            Err(RendererError::TextureDataSize { .. })
+        ));
     }
 }